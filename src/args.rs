@@ -0,0 +1,731 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+use crate::row::Columns;
+use crate::snapshot::{DEFAULT_EBIKE_VEHICLE_TYPES, DEFAULT_STATIONS_KEY, GZIP_MAGIC};
+
+#[derive(Debug, Parser)]
+#[command(about = "Collects station_status snapshots into Apache Parquet")]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Glob of gzipped station_status snapshots to collect. Files whose
+    /// name has another GBFS feed's in it, like `free_bike_status`, are
+    /// skipped, and so are files that turn out to hold another feed's
+    /// document, so a directory mixing feeds can be collected as it is.
+    #[arg(long, default_value = "./station_status/*.json.gz")]
+    pub input: String,
+
+    /// Skip the `--input` files up to and including this one, in sorted
+    /// order, to pick a long backfill up where it stopped.
+    #[arg(long, conflicts_with = "input_tar")]
+    pub resume_from_file: Option<PathBuf>,
+
+    /// Read snapshots from the `.json` and `.json.gz` members of this tar
+    /// archive (optionally gzipped) instead of `--input`.
+    #[arg(long)]
+    pub input_tar: Option<PathBuf>,
+
+    /// Collect exactly the files listed in this file, one path per line in
+    /// the order given, instead of `--input`. Blank lines and lines starting
+    /// with `#` are skipped, and relative paths are taken from the current
+    /// directory. A listed file that's missing is handled per `--on-error`.
+    #[arg(long, conflicts_with_all = ["input", "input_tar"])]
+    pub input_list: Option<PathBuf>,
+
+    /// Parquet file to write. It is written as `<output>.partial` and renamed
+    /// once complete; a run that fails after writing rows leaves the partial
+    /// file behind.
+    #[arg(long, default_value = "data.parquet")]
+    pub output: PathBuf,
+
+    /// Where to write the station id to integer mapping.
+    #[arg(long, default_value = "id_map.json", global = true)]
+    pub id_map: PathBuf,
+
+    /// How `station_ids` is stored: integers looked up in `--id-map`, or the
+    /// provider's ids themselves, dictionary encoded, with no id map written.
+    /// Each row group's dictionary holds every station seen so far, so it
+    /// costs more per row group on systems with many stations.
+    #[arg(long, value_enum, default_value_t = StationIdType::MappedU16)]
+    pub station_id_type: StationIdType,
+
+    /// Also upsert rows into this DuckDB database, skipping any
+    /// (station, time) already present.
+    #[arg(long)]
+    pub append_to_duckdb: Option<PathBuf>,
+
+    /// Rows buffered before they are written out as a row group. Rows from
+    /// every input file share the buffer, so many small snapshots still make
+    /// full row groups.
+    #[arg(long, default_value_t = 1024 * 1024)]
+    pub row_group_size: usize,
+
+    /// Also start a new row group whenever the snapshot time enters the next
+    /// window of this length, counted from the epoch, e.g. `1h` or `1d`, so
+    /// each row group's time statistics cover a single window.
+    #[arg(long, value_parser = parse_duration)]
+    pub row_group_by: Option<Duration>,
+
+    /// Also write a row group early once the buffered rows are estimated to
+    /// take this many bytes.
+    #[arg(long)]
+    pub max_memory: Option<usize>,
+
+    /// Fail once the column builders hold more than this many bytes,
+    /// counting rows allocated for up front, rather than growing until the
+    /// system runs out of memory. Measured like `--max-memory`.
+    #[arg(long)]
+    pub arrow_memory_pool_limit: Option<usize>,
+
+    /// Rows to allocate column builders for up front, to save growing them
+    /// as rows arrive. Defaults to the first snapshot's station count times
+    /// the number of input files, at most `--row-group-size`; 0 leaves them
+    /// to grow.
+    #[arg(long)]
+    pub builder_capacity: Option<usize>,
+
+    /// Write the run summary here as JSON.
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+
+    /// Record a SHA-256 of every input file in the manifest.
+    #[arg(long)]
+    pub checksum: bool,
+
+    /// Replace an existing output, id map or manifest instead of refusing
+    /// to run.
+    #[arg(long, global = true)]
+    pub overwrite: bool,
+
+    /// Write the rows as parquet, a JSON array of objects, or one JSON
+    /// object per line. JSON keys are the schema's column names, nulls
+    /// included, and times are ISO 8601.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Parquet)]
+    pub format: OutputFormat,
+
+    /// Parquet compression codec. `auto` tries snappy and zstd on the first
+    /// row group and keeps the better one for the rest of the file.
+    #[arg(long, value_enum, default_value_t = Codec::Uncompressed, global = true)]
+    pub compression: Codec,
+
+    /// How much `--compression auto` weighs write time against size: 0 picks
+    /// the smallest output, 1 counts being twice as slow as much as being
+    /// twice as large.
+    #[arg(long, default_value_t = 0.25)]
+    pub compression_time_weight: f64,
+
+    /// When the output is fsynced: after every row group, once when it's
+    /// closed, or never, leaving it to the OS. Syncing every row group
+    /// keeps what was written through a power loss, at the cost of speed.
+    #[arg(long, value_enum, default_value_t = Durability::None)]
+    pub durability: Durability,
+
+    /// Parquet format version to write. 1.0 leaves out the newer encodings
+    /// and data page format, for readers that predate them.
+    #[arg(long, value_enum, default_value_t = ParquetVersion::V1, global = true)]
+    pub parquet_version: ParquetVersion,
+
+    /// Print the files, outputs, schema and estimated row count, then exit
+    /// without writing anything.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Print the earliest and latest snapshot times and how many snapshots
+    /// there are, then exit, counting the files collecting would. Reads the
+    /// time of a file named station_status from its name when it is a unix
+    /// time and otherwise only `last_updated`, so it's much quicker than
+    /// `--dry-run`; files whose names don't say their feed are read in
+    /// full to tell them from other feeds.
+    #[arg(long, conflicts_with = "dry_run")]
+    pub time_span: bool,
+
+    /// Before reading any input, check that the output's directory can be
+    /// written to, and warn if its free space is less than the inputs'
+    /// size, so a long run doesn't fail hours in.
+    #[arg(long, conflicts_with_all = ["dry_run", "time_span"])]
+    pub preflight: bool,
+
+    /// Also write a uniform random sample of rows here, as JSON lines.
+    #[arg(long)]
+    pub sample_out: Option<PathBuf>,
+
+    /// How many rows `--sample-out` keeps.
+    #[arg(long, default_value_t = 100, requires = "sample_out")]
+    pub sample_n: usize,
+
+    /// Seeds the random number generator, so the same inputs and seed give
+    /// the same output. Only `--sample-out` draws random numbers;
+    /// `--compression auto` picks by measured size and write time, so its
+    /// choice can still vary between runs.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// List stations whose bikes plus docks available were zero in every
+    /// observation, as JSON, here or in `empty_stations.json`.
+    #[arg(long, num_args = 0..=1, default_missing_value = "empty_stations.json")]
+    pub emit_empty_stations_report: Option<PathBuf>,
+
+    /// Write which row groups of the parquet output each mapped station id
+    /// has rows in, with each row group's byte range, as JSON, here or in
+    /// `index.json`.
+    #[arg(long, num_args = 0..=1, default_missing_value = "index.json")]
+    pub emit_index: Option<PathBuf>,
+
+    /// Report how far each file's modification time is from the feed's
+    /// `last_updated`, as JSON, here or in `skew.json`.
+    #[arg(long, num_args = 0..=1, default_missing_value = "skew.json")]
+    pub report_skew: Option<PathBuf>,
+
+    /// Report how many collected snapshots had each number of active
+    /// stations, with the fewest, median and most, as JSON, here or in
+    /// `station_counts.json`. Counts well under the median are partial
+    /// outages.
+    #[arg(long, num_args = 0..=1, default_missing_value = "station_counts.json")]
+    pub report_station_counts: Option<PathBuf>,
+
+    /// Describe each output column's unit, meaning and nullability under
+    /// the flags this run used, as JSON, here or in `columns.json`.
+    #[arg(long, num_args = 0..=1, default_missing_value = "columns.json")]
+    pub columns_metadata: Option<PathBuf>,
+
+    /// Write the station_information and free_bike_status snapshots among
+    /// the inputs, told apart by name or content, to `<feed>.parquet` in
+    /// this directory rather than skipping them. Other feeds are still
+    /// skipped.
+    #[arg(long)]
+    pub other_feeds_dir: Option<PathBuf>,
+
+    /// List every adjustment made to the data (nulled counts, skipped
+    /// snapshots and so on) as JSON, here or in `warnings.json`.
+    #[arg(long, num_args = 0..=1, default_missing_value = "warnings.json")]
+    pub warnings_out: Option<PathBuf>,
+
+    /// Exit non-zero if the data needed any adjustment, once every output
+    /// has been written, so a feed regression fails CI.
+    #[arg(long)]
+    pub fail_on_warning: bool,
+
+    /// Seconds of skew, either way, past which `--report-skew` flags a file.
+    #[arg(long, default_value_t = 300)]
+    pub skew_threshold: i64,
+
+    /// What to do with a snapshot that can't be decompressed or parsed.
+    #[arg(long, value_enum, default_value_t = OnError::Skip)]
+    pub on_error: OnError,
+
+    /// Validate every snapshot against this GBFS `station_status` JSON
+    /// Schema. Documents that fail are handled per `--on-error`.
+    #[arg(long)]
+    pub gbfs_schema: Option<PathBuf>,
+
+    /// Strip this prefix from station ids (e.g. `bcycle_madison_`) before
+    /// they are filtered and mapped.
+    #[arg(long)]
+    pub strip_id_prefix: Option<String>,
+
+    /// Only collect these stations: a comma separated list of provider
+    /// station ids, or a file with one per line.
+    #[arg(long)]
+    pub stations: Option<String>,
+
+    /// Never collect these stations, in the same format as `--stations`.
+    #[arg(long)]
+    pub exclude_stations: Option<String>,
+
+    /// Add a `source_file` column naming the snapshot each row came from.
+    #[arg(long)]
+    pub with_source: bool,
+
+    /// Add a `num_bikes_usable` column: bikes available less bikes
+    /// disabled, never below zero.
+    #[arg(long)]
+    pub with_usable_bikes: bool,
+
+    /// Add a `last_reported` timestamp column, read as seconds or
+    /// milliseconds by its size and null when the feed sends 0 or nothing.
+    #[arg(long)]
+    pub with_last_reported: bool,
+
+    /// Add an `eightd_has_available_keys` column, for systems whose stations
+    /// dispense keys; null where the feed leaves it out.
+    #[arg(long = "with-8d")]
+    pub with_8d: bool,
+
+    /// Add an `occupancy_rolling_mean` column: each station's mean share of
+    /// bikes in bikes plus docks available over this long up to the row,
+    /// e.g. `15m`. It's a computed column, so not with `--raw`.
+    #[arg(long, value_parser = parse_duration, conflicts_with = "raw")]
+    pub rolling_window: Option<Duration>,
+
+    /// How far apart a station's rows can be before `--rolling-window`
+    /// treats the space between them as a gap. The default lets a feed
+    /// saved every minute miss one snapshot.
+    #[arg(long, value_parser = parse_duration, default_value = "2m", requires = "rolling_window")]
+    pub rolling_max_gap: Duration,
+
+    /// What a gap does to `occupancy_rolling_mean`.
+    #[arg(long, value_enum, default_value_t = RollingGap::Reset, requires = "rolling_window")]
+    pub rolling_on_gap: RollingGap,
+
+    /// Add an `avail_<vehicle_type_id>` column for every vehicle type any
+    /// station lists in `vehicle_types_available`, 0 where a station
+    /// doesn't list it. Finding the types takes a pass over the inputs of
+    /// its own.
+    #[arg(long, conflicts_with = "raw")]
+    pub split_vehicle_availability_by_type: bool,
+
+    /// Rename output columns, as comma separated `from=to` pairs like
+    /// `times=ts,num_bikes_available=bikes`. Each `from` is a column's
+    /// usual name, whatever else is renamed to it. The subcommands that
+    /// read an output back expect the usual names.
+    #[arg(long, value_delimiter = ',', value_parser = parse_rename)]
+    pub rename: Vec<(String, String)>,
+
+    /// Parse snapshots with simd-json (requires the `simd` feature), falling
+    /// back to serde_json for documents it rejects.
+    #[arg(long)]
+    pub fast_json: bool,
+
+    /// Where the stations array is in each document, as a dotted path, for
+    /// feeds that nest it under something like `data.bikes`.
+    #[arg(long, default_value = DEFAULT_STATIONS_KEY)]
+    pub stations_key: String,
+
+    /// Bytes to read from disk at a time, for each snapshot and for
+    /// `--input-tar`. Larger reads can help on slow disks.
+    #[arg(long, default_value_t = 8 * 1024, value_parser = parse_buffer_size)]
+    pub read_buffer_size: usize,
+
+    /// Bytes to set aside up front for each decompressed snapshot, rather
+    /// than growing the buffer as the document is decoded.
+    #[arg(long)]
+    pub decompress_buffer_size: Option<usize>,
+
+    /// Suppress all non-error output.
+    #[arg(long)]
+    pub quiet: bool,
+
+    /// Log per-file timings (same as `RUST_LOG=debug`).
+    #[arg(long, conflicts_with = "quiet")]
+    pub verbose: bool,
+
+    /// Time decompression, parsing, id lookups, builder appends and writes
+    /// over the run and list each with its share of the total.
+    #[arg(long)]
+    pub profile: bool,
+
+    /// How many of the slowest files to list in the summary.
+    #[arg(long, default_value_t = 5)]
+    pub slowest: usize,
+
+    /// Output format for log events and the final summary.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Seed station ids from an existing `--id-map` file, so ids stay stable
+    /// across runs.
+    #[arg(long)]
+    pub reuse_id_map: bool,
+
+    /// Write id_map.json indented and ordered by mapped id, so it diffs
+    /// cleanly between runs.
+    #[arg(long, global = true)]
+    pub pretty_id_map: bool,
+
+    /// Rewrite the id map after every row group rather than only at the
+    /// end, so a run that dies partway leaves one covering every row
+    /// written to the `.partial` output.
+    #[arg(long)]
+    pub checkpoint_id_map: bool,
+
+    /// Share the id map with other collectors running at the same time.
+    /// New stations are numbered with `<id-map>.lock` locked, after
+    /// re-reading the map for ids the others have handed out, and the map
+    /// is written back straight away, so no two stations get one id.
+    #[arg(long, requires = "reuse_id_map")]
+    pub coalesce_id_map_writes_with_lock: bool,
+
+    /// Bike counts above this (available, ebikes, disabled) are treated as
+    /// a feed sentinel and stored as null, as are negative counts.
+    #[arg(long, default_value_t = 10000)]
+    pub max_bikes: u16,
+
+    /// Like `--max-bikes`, for `num_docks_available`.
+    #[arg(long, default_value_t = 10000)]
+    pub max_docks: u16,
+
+    /// Skip snapshots with fewer active stations than this, like the ones a
+    /// half-up feed backend sends.
+    #[arg(long)]
+    pub min_stations: Option<usize>,
+
+    /// Also skip snapshots with fewer active stations than this fraction of
+    /// the median over the snapshots ingested so far.
+    #[arg(long)]
+    pub min_stations_fraction: Option<f64>,
+
+    /// Whether the parquet output carries classic bikes only, as
+    /// `num_classic_bikes_available`, or the feed's `num_bikes_available`.
+    #[arg(long, value_enum, default_value_t = BikeSplit::Classic)]
+    pub bike_split: BikeSplit,
+
+    /// Vehicle type ids whose disabled bikes are e-bikes, comma-separated,
+    /// when a station breaks `num_bikes_disabled` down by
+    /// `vehicle_types_available`; every other type is classic. The ids are
+    /// each system's own, listed in its vehicle_types.json with a
+    /// `propulsion_type`. The default, 2, is the e-bike type in the feeds
+    /// this collector was written for.
+    #[arg(long, value_delimiter = ',', default_value = DEFAULT_EBIKE_VEHICLE_TYPES, global = true)]
+    pub ebike_vehicle_types: Vec<String>,
+
+    /// Store the feed's values as reported, to check against the JSON:
+    /// `num_bikes_available` as the total whatever `--bike-split` says,
+    /// `last_updated` to the second rather than rounded to a minute, and
+    /// counts above `--max-bikes`/`--max-docks` kept. Counts that don't fit
+    /// a u16, like -1, are still null, and so is the disabled breakdown,
+    /// which is worked out from `vehicle_types_available`.
+    #[arg(long, conflicts_with = "with_usable_bikes")]
+    pub raw: bool,
+
+    /// Skip snapshots from before this time: RFC 3339, or a UTC date like
+    /// `2023-07-01`.
+    #[arg(long, value_parser = parse_from)]
+    pub from: Option<i64>,
+
+    /// Skip snapshots up to the `max_time` recorded in the previous
+    /// `--manifest`, so a scheduled run only collects what is new.
+    #[arg(long, requires = "manifest")]
+    pub since_last_run: bool,
+
+    /// Keep only the last this many days in the output: rows from the
+    /// existing output within that long of the newest snapshot are carried
+    /// into the rewritten file ahead of the new ones, and older rows are
+    /// dropped. Snapshots the existing output already covers are skipped.
+    #[arg(long, requires = "reuse_id_map", conflicts_with = "emit_index")]
+    pub window_days: Option<u32>,
+
+    /// How a snapshot's `last_updated` is snapped to a whole minute.
+    #[arg(long, value_enum, default_value_t = TimeRounding::Floor)]
+    pub time_rounding: TimeRounding,
+
+    /// What to do with a snapshot whose `last_updated` is older than one
+    /// already collected, as after a provider clock correction, so `times`
+    /// never goes backwards in file order.
+    #[arg(long, value_enum)]
+    pub enforce_monotonic_time: Option<MonotonicTime>,
+
+    /// Leave out a station's row when its counts and `is_returning` match
+    /// the last row kept for it less than this long ago, e.g. `2m`. The
+    /// kept row doesn't move forward, so an unchanging station still gets a
+    /// row every window.
+    #[arg(long, value_parser = parse_duration)]
+    pub dedup_window: Option<Duration>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the footer metadata of an output, including the stats written
+    /// at close, without reading any rows.
+    Info {
+        /// Parquet file to inspect.
+        path: PathBuf,
+    },
+    /// Count the nulls in every column of an output, failing if a column
+    /// the collector never leaves null has any.
+    Verify {
+        /// Parquet file to check.
+        path: PathBuf,
+    },
+    /// Check an output and the `--id-map` it was written with against each
+    /// other, failing if an id in the output has no station in the map or
+    /// two stations share an id. Stations in the map with no rows are
+    /// listed, since `--reuse-id-map` keeps stations that stopped appearing.
+    CheckConsistency {
+        /// Parquet output to check. Its `station_ids` must be mapped
+        /// integers.
+        #[arg(long)]
+        parquet: PathBuf,
+    },
+    /// Rewrite many small outputs into one file with fewer, larger row
+    /// groups, compressed per `--compression`.
+    Compact {
+        /// Glob of parquet files to compact.
+        input: String,
+
+        /// Compacted file to write.
+        #[arg(long)]
+        output: PathBuf,
+
+        /// Rows per row group in the compacted file.
+        #[arg(long, default_value_t = 1024 * 1024)]
+        target_row_group_size: usize,
+
+        /// Remove the inputs once the compacted file's row count matches.
+        #[arg(long)]
+        delete_inputs: bool,
+    },
+    /// Split an output into one `station=<id>/data.parquet` per station,
+    /// named by the provider ids in `--id-map` when it exists.
+    SplitByStation {
+        /// Parquet file to split.
+        path: PathBuf,
+
+        /// Directory to write the per-station files under.
+        #[arg(long)]
+        out_dir: PathBuf,
+
+        /// Keep at most this many files open at once, the input included,
+        /// writing stations whose file had to be closed to part files that
+        /// are merged at the end. Each close and reopen starts another part,
+        /// so a limit well under the station count is much slower.
+        #[arg(long)]
+        max_files_open: Option<usize>,
+    },
+    /// Write the rows for one time back out as a station_status document.
+    ExportSnapshot {
+        /// Parquet file to read.
+        path: PathBuf,
+
+        /// The time to export, RFC 3339 or a UTC date; the nearest stored
+        /// time is used.
+        #[arg(long, value_parser = parse_from)]
+        time: i64,
+
+        /// Where to write the document.
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Combine id maps from separate runs into one, renumbering so no two
+    /// stations share an id.
+    MergeIdMaps {
+        /// Id maps to combine, numbered in this order.
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Combined id map to write.
+        #[arg(long)]
+        output: PathBuf,
+
+        /// Where to write each input's table from its old ids to the new
+        /// ones.
+        #[arg(long, default_value = "remap.json")]
+        remap_out: PathBuf,
+
+        /// Prefix every station id with the name of its map's directory, so
+        /// the same provider id in two systems stays two stations.
+        #[arg(long)]
+        namespace_by_system: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnError {
+    /// Abort the run with an error.
+    Fail,
+    /// Log the file and carry on without it.
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Parquet,
+    Json,
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Durability {
+    None,
+    /// Needs `--format parquet`, whose row groups go straight to the file.
+    Batch,
+    Close,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RollingGap {
+    /// Start the mean over from the first row after the gap.
+    Reset,
+    /// Leave the mean null until the window no longer reaches into the gap.
+    Null,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ParquetVersion {
+    #[value(name = "1.0")]
+    V1,
+    #[value(name = "2.0")]
+    V2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Codec {
+    Uncompressed,
+    Snappy,
+    Zstd,
+    Auto,
+}
+
+impl Codec {
+    /// The name stored as `compression` in the footer.
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Uncompressed => "uncompressed",
+            Codec::Snappy => "snappy",
+            Codec::Zstd => "zstd",
+            Codec::Auto => "auto",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StationIdType {
+    /// `UInt16`, mapped back to provider ids by id_map.json.
+    #[value(name = "mapped-u16")]
+    MappedU16,
+    /// `Dictionary(Int32, Utf8)` of the provider ids.
+    Dictionary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BikeSplit {
+    /// Total less ebikes, as `num_classic_bikes_available`.
+    Classic,
+    /// The feed's total, ebikes included, as `num_bikes_available`.
+    Total,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MonotonicTime {
+    /// Skip the snapshot.
+    Drop,
+    /// Move the snapshot to the minute after the newest time so far. Not
+    /// the newest time itself, which already has its snapshot and would
+    /// make this one a duplicate.
+    Clamp,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TimeRounding {
+    #[default]
+    Floor,
+    Round,
+    Ceil,
+}
+
+impl TimeRounding {
+    /// Snaps a unix timestamp in seconds to a minute boundary.
+    pub fn apply(self, seconds: i64) -> i64 {
+        let remainder = seconds.rem_euclid(60);
+        let floor = seconds - remainder;
+        match self {
+            TimeRounding::Floor => floor,
+            TimeRounding::Round if remainder >= 30 => floor + 60,
+            TimeRounding::Round => floor,
+            TimeRounding::Ceil if remainder > 0 => floor + 60,
+            TimeRounding::Ceil => floor,
+        }
+    }
+}
+
+/// Parses `--from` or `--time` into milliseconds since the epoch.
+fn parse_from(value: &str) -> Result<i64, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.timestamp_millis());
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_time(NaiveTime::MIN).timestamp_millis())
+        .map_err(|_| format!("expected an RFC 3339 time or a YYYY-MM-DD date, got {value:?}"))
+}
+
+/// Parses a positive whole number of seconds, minutes, hours or days, like
+/// `90s`, `15m`, `1h` or `7d`.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("expected a duration like 90s, 15m, 1h or 7d, got {value:?}");
+    let split = value.len().checked_sub(1).ok_or_else(invalid)?;
+    let (count, unit) = value.split_at(split);
+    let count: i64 = count.parse().map_err(|_| invalid())?;
+    let duration = match unit {
+        "s" => Duration::seconds(count),
+        "m" => Duration::minutes(count),
+        "h" => Duration::hours(count),
+        "d" => Duration::days(count),
+        _ => return Err(invalid()),
+    };
+    if duration <= Duration::zero() {
+        return Err(invalid());
+    }
+    Ok(duration)
+}
+
+/// Parses one `from=to` pair of `--rename`.
+fn parse_rename(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+            Ok((from.to_string(), to.to_string()))
+        }
+        _ => Err(format!("expected a rename like times=ts, got {value:?}")),
+    }
+}
+
+/// Parses a read buffer size, which must hold the two bytes of the gzip
+/// magic number that the format is detected by.
+fn parse_buffer_size(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(size) if size >= GZIP_MAGIC.len() => Ok(size),
+        _ => Err(format!(
+            "expected a buffer size of at least {} bytes, got {value:?}",
+            GZIP_MAGIC.len()
+        )),
+    }
+}
+
+impl Args {
+    pub fn columns(&self) -> Columns {
+        Columns {
+            source_file: self.with_source,
+            usable_bikes: self.with_usable_bikes,
+            classic_bikes: !self.raw && self.bike_split == BikeSplit::Classic,
+            station_id_dictionary: self.station_id_type == StationIdType::Dictionary,
+            last_reported: self.with_last_reported,
+            eightd_has_available_keys: self.with_8d,
+            rolling_occupancy: self.rolling_window.is_some(),
+            raw: self.raw,
+            time_rounding: self.time_rounding,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_rounding_snaps_to_a_minute() {
+        let at = |second: i64| 1_688_000_040 + second;
+        for (rounding, expected) in [
+            (TimeRounding::Floor, [at(0), at(0), at(0)]),
+            (TimeRounding::Round, [at(0), at(60), at(60)]),
+            (TimeRounding::Ceil, [at(60), at(60), at(60)]),
+        ] {
+            let rounded = [29, 30, 31].map(|second| rounding.apply(at(second)));
+            assert_eq!(rounded, expected, "{rounding:?}");
+        }
+        assert_eq!(TimeRounding::Ceil.apply(at(0)), at(0));
+    }
+
+    #[test]
+    fn raw_takes_no_rolling_window() {
+        let err = Args::try_parse_from(["bike-1", "--raw", "--rolling-window", "5m"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        Args::try_parse_from(["bike-1", "--rolling-window", "5m"]).unwrap();
+    }
+}
@@ -0,0 +1,2973 @@
+use arrow::array::as_primitive_array;
+use arrow::datatypes::UInt16Type;
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::json::writer::record_batches_to_json_rows;
+use arrow::json::{ArrayWriter, LineDelimitedWriter};
+use arrow_array::{Array, RecordBatch, StringArray};
+use chrono::{Duration, NaiveDateTime};
+use parquet::arrow::ArrowWriter;
+use parquet::file::metadata::KeyValue;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+use crate::args::{Codec, Durability, MonotonicTime, OnError, OutputFormat, StationIdType};
+use crate::compression::{pick_codec, properties};
+use crate::duckdb_sink::DuckDbSink;
+use crate::feeds::FeedOutputs;
+use crate::index::RowGroupIndex;
+use crate::profile::{Phase, Profile, Profiler};
+use crate::rolling::RollingOccupancy;
+use crate::row::{check_schema, describe, rename, schema, Builders, Columns, Row, SCHEMA_VERSION};
+use crate::sample::Reservoir;
+use crate::skew::SkewReport;
+use crate::snapshot::{
+    epoch_millis, for_each_feed, input_paths, FileTiming, Input, OtherFeed, Snapshot, Station,
+};
+use crate::transform::{RowBuilder, RowTransform};
+use crate::vehicle_types::{self, VehicleTypeCounts};
+use crate::warnings::{WarningKind, Warnings};
+use crate::window::SlidingWindow;
+use crate::{Args, Result};
+
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub files: usize,
+    pub rows: usize,
+    pub stations: usize,
+    pub duplicate_snapshots: usize,
+    /// Snapshots from before `--from` or the previous run's `max_time`.
+    pub skipped_old_snapshots: usize,
+    /// Snapshots skipped for having too few active stations.
+    pub degenerate_snapshots: usize,
+    /// Snapshots older than one already collected, under
+    /// `--enforce-monotonic-time`.
+    pub backwards_snapshots: usize,
+    /// Rows left out under `--dedup-window` for repeating their station's
+    /// last kept row.
+    pub near_duplicate_rows: usize,
+    /// Adjustments made to the data, as listed by `--warnings-out`.
+    pub warnings: usize,
+    pub skipped_files: usize,
+    /// Snapshots of other feeds written out under `--other-feeds-dir`, and
+    /// the rows each feed's output got.
+    pub other_feed_files: usize,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub other_feed_rows: BTreeMap<String, usize>,
+    pub row_groups: usize,
+    pub rewritten_ids: usize,
+    pub duckdb_inserted: usize,
+    /// Times the output was fsynced, per `--durability`.
+    pub syncs: usize,
+    /// Rows of the existing output kept and dropped under `--window-days`.
+    pub window_kept_rows: usize,
+    pub window_dropped_rows: usize,
+    /// Newest snapshot time collected so far, in milliseconds since the
+    /// epoch. Read back by `--since-last-run`.
+    pub max_time: Option<i64>,
+    pub slowest_files: Vec<FileTiming>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+    /// Stations left out of collected snapshots for not being active, by
+    /// their `station_status`.
+    pub inactive_stations: BTreeMap<String, usize>,
+    /// The vehicle types found under `--split-vehicle-availability-by-type`,
+    /// in column order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub vehicle_types: Vec<String>,
+    /// Where the run's time went, with `--profile`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<Profile>,
+}
+
+/// Serializes the id legend as `{station_id: id}` in ascending id order.
+pub(crate) struct SortedIdMap<'a>(pub BTreeMap<u16, &'a str>);
+
+impl Serialize for SortedIdMap<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(id, station_id)| (station_id, id)))
+    }
+}
+
+/// The `--stations` allowlist and `--exclude-stations` blocklist, matched
+/// against the provider's `station_id`.
+#[derive(Debug, Default)]
+pub struct StationFilter {
+    allow: Option<HashSet<String>>,
+    block: HashSet<String>,
+}
+
+impl StationFilter {
+    pub fn from_args(args: &Args) -> Result<Self> {
+        Ok(StationFilter {
+            allow: args.stations.as_deref().map(station_list).transpose()?,
+            block: match &args.exclude_stations {
+                Some(list) => station_list(list)?,
+                None => HashSet::new(),
+            },
+        })
+    }
+
+    pub fn keeps(&self, station_id: &str) -> bool {
+        self.allow
+            .as_ref()
+            .is_none_or(|allow| allow.contains(station_id))
+            && !self.block.contains(station_id)
+    }
+}
+
+/// A station list is either a path to a file with one id per line, or the
+/// ids themselves separated by commas.
+fn station_list(value: &str) -> Result<HashSet<String>> {
+    let ids = if Path::new(value).is_file() {
+        std::fs::read_to_string(value)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect()
+    } else {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    Ok(ids)
+}
+
+/// Fails if a run would clobber an existing output, unless `--overwrite`
+/// is passed. The id map under `--reuse-id-map`, the manifest under
+/// `--since-last-run` and the output under `--window-days` are read back in
+/// and expected to be replaced.
+pub fn check_overwrite(args: &Args) -> Result<()> {
+    if args.overwrite {
+        return Ok(());
+    }
+    let mut outputs = Vec::new();
+    if args.window_days.is_none() {
+        outputs.push(args.output.as_path());
+    }
+    if !args.reuse_id_map && args.station_id_type == StationIdType::MappedU16 {
+        outputs.push(&args.id_map);
+    }
+    if let Some(manifest) = args.manifest.as_deref().filter(|_| !args.since_last_run) {
+        outputs.push(manifest);
+    }
+    outputs
+        .into_iter()
+        .try_for_each(|output| refuse_overwrite(args, output))
+}
+
+/// Fails if `path` exists, unless `--overwrite` is passed.
+pub(crate) fn refuse_overwrite(args: &Args, path: &Path) -> Result<()> {
+    if path.exists() && !args.overwrite {
+        return Err(format!(
+            "refusing to overwrite existing {} (pass --overwrite to replace it)",
+            path.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Reads an id map, from provider station id to mapped id.
+pub(crate) fn read_id_map(path: &Path) -> Result<HashMap<String, u16>> {
+    serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map_err(|err| format!("reading {}: {err}", path.display()).into())
+}
+
+/// The `max_time` recorded in a previous run's manifest, if there is one.
+fn last_run_max_time(manifest: &Path) -> Result<Option<i64>> {
+    if !manifest.exists() {
+        return Ok(None);
+    }
+    let previous: serde_json::Value =
+        serde_json::from_reader(BufReader::new(File::open(manifest)?))?;
+    Ok(previous.get("max_time").and_then(serde_json::Value::as_i64))
+}
+
+/// The `schema_version` an existing output was written with: `None` if
+/// there is no readable output, `Some(None)` if it predates the key.
+fn stored_schema_version(path: &Path) -> Option<Option<String>> {
+    let reader = SerializedFileReader::new(File::open(path).ok()?).ok()?;
+    let version = reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .and_then(|kvs| kvs.iter().find(|kv| kv.key == "schema_version"))
+        .and_then(|kv| kv.value.clone());
+    Some(version)
+}
+
+/// Aggregates over the written rows, stored in the footer as `stats.*` so
+/// `info` can report them without scanning the data.
+#[derive(Debug, Default)]
+struct Stats {
+    rows: u64,
+    stations: HashSet<u16>,
+    min_bikes_available: Option<u16>,
+    max_bikes_available: Option<u16>,
+    total_bikes_available: u64,
+    /// Rows with a non-null `num_bikes_available`, for the mean.
+    bikes_counted: u64,
+}
+
+impl Stats {
+    fn add(&mut self, row: &Row) {
+        self.count(row.station_id, row.num_bikes_available);
+    }
+
+    fn count(&mut self, station_id: u16, bikes: Option<u16>) {
+        self.rows += 1;
+        self.stations.insert(station_id);
+        let Some(bikes) = bikes else {
+            return;
+        };
+        self.bikes_counted += 1;
+        self.min_bikes_available =
+            Some(self.min_bikes_available.map_or(bikes, |min| min.min(bikes)));
+        self.max_bikes_available =
+            Some(self.max_bikes_available.map_or(bikes, |max| max.max(bikes)));
+        self.total_bikes_available += u64::from(bikes);
+    }
+
+    /// Adds rows carried over from the existing output under
+    /// `--window-days`. With `classic_bikes` the feed's total is rebuilt as
+    /// classic plus ebikes, which is off only where the feed had more
+    /// ebikes than bikes.
+    fn add_batch(&mut self, batch: &RecordBatch, classic_bikes: bool) {
+        let column = |index| as_primitive_array::<UInt16Type>(batch.column(index));
+        let (station_ids, bikes, ebikes) = (column(0), column(1), column(2));
+        for index in 0..batch.num_rows() {
+            let bikes = (!bikes.is_null(index)).then(|| bikes.value(index));
+            let total = if classic_bikes {
+                bikes
+                    .zip((!ebikes.is_null(index)).then(|| ebikes.value(index)))
+                    .map(|(classic, ebikes)| classic.saturating_add(ebikes))
+            } else {
+                bikes
+            };
+            self.count(station_ids.value(index), total);
+        }
+    }
+
+    fn key_values(&self) -> Vec<KeyValue> {
+        let mut kvs = vec![
+            ("stats.rows", self.rows.to_string()),
+            ("stats.stations", self.stations.len().to_string()),
+        ];
+        if let (Some(min), Some(max)) = (self.min_bikes_available, self.max_bikes_available) {
+            let mean = self.total_bikes_available as f64 / self.bikes_counted as f64;
+            kvs.push(("stats.num_bikes_available.min", min.to_string()));
+            kvs.push(("stats.num_bikes_available.max", max.to_string()));
+            kvs.push(("stats.num_bikes_available.mean", format!("{mean:.3}")));
+        }
+        kvs.into_iter()
+            .map(|(key, value)| KeyValue::new(key.to_string(), value))
+            .collect()
+    }
+}
+
+/// Active station counts of the snapshots ingested so far, for the running
+/// median behind `--min-stations-fraction` and `--report-station-counts`.
+#[derive(Debug, Default)]
+struct StationCounts {
+    counts: BTreeMap<usize, usize>,
+    snapshots: usize,
+}
+
+impl StationCounts {
+    fn add(&mut self, stations: usize) {
+        *self.counts.entry(stations).or_default() += 1;
+        self.snapshots += 1;
+    }
+
+    /// The lower median. Walks the distinct counts, of which a feed has few.
+    fn median(&self) -> Option<usize> {
+        let mut rank = self.snapshots.div_ceil(2);
+        for (&stations, &snapshots) in &self.counts {
+            if rank <= snapshots {
+                return Some(stations);
+            }
+            rank -= snapshots;
+        }
+        None
+    }
+
+    /// Writes the `--report-station-counts` report.
+    fn write(&self, path: &Path) -> std::io::Result<()> {
+        #[derive(Serialize)]
+        struct Report<'a> {
+            snapshots: usize,
+            min: Option<usize>,
+            median: Option<usize>,
+            max: Option<usize>,
+            /// Snapshots by their number of active stations.
+            histogram: &'a BTreeMap<usize, usize>,
+        }
+        let report = Report {
+            snapshots: self.snapshots,
+            min: self.counts.keys().next().copied(),
+            median: self.median(),
+            max: self.counts.keys().next_back().copied(),
+            histogram: &self.counts,
+        };
+        serde_json::to_writer_pretty(File::create(path)?, &report)?;
+        Ok(())
+    }
+}
+
+/// How often a station was seen, and whether it ever had a bike or a free
+/// dock, for `--emit-empty-stations-report`.
+#[derive(Debug, Default)]
+struct Occupancy {
+    observations: usize,
+    ever_occupied: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct EmptyStation<'a> {
+    station_id: &'a str,
+    id: u16,
+    observations: usize,
+}
+
+/// Where the rows are written, per `--format`.
+enum Sink {
+    Parquet(Box<ArrowWriter<File>>),
+    Json(ArrayWriter<BufWriter<File>>),
+    Ndjson(LineDelimitedWriter<BufWriter<File>>),
+}
+
+impl Sink {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            Sink::Parquet(writer) => {
+                writer.write(batch)?;
+                writer.flush()?;
+            }
+            Sink::Json(writer) => {
+                for row in json_rows(batch)? {
+                    writer.write_row(&row)?;
+                }
+            }
+            Sink::Ndjson(writer) => {
+                for row in json_rows(batch)? {
+                    writer.write_row(&row)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finishes the file: the parquet footer, or the end of the JSON array.
+    fn close(self, empty: bool) -> Result<()> {
+        match self {
+            Sink::Parquet(writer) => {
+                // Unlike close, this flushes the writer's buffer rather than
+                // dropping it, so the footer failing to write is an error.
+                writer.into_inner()?;
+            }
+            Sink::Json(mut writer) => {
+                writer.finish()?;
+                let mut file = writer.into_inner();
+                // The writer only opens the array once it has a row.
+                if empty {
+                    file.write_all(b"[]")?;
+                }
+                file.flush()?;
+            }
+            Sink::Ndjson(writer) => writer.into_inner().flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// `batch` as JSON objects with a key for every column. arrow-json leaves
+/// out the keys of null values, so they are put back as nulls.
+fn json_rows(batch: &RecordBatch) -> Result<Vec<serde_json::Value>> {
+    let schema = batch.schema();
+    let rows = record_batches_to_json_rows(&[batch])?;
+    Ok(rows
+        .into_iter()
+        .map(|mut row| {
+            for field in schema.fields() {
+                row.entry(field.name().as_str())
+                    .or_insert(serde_json::Value::Null);
+            }
+            serde_json::Value::Object(row)
+        })
+        .collect())
+}
+
+/// Where the output is written until the run succeeds, and where it is
+/// left if writing it fails. The id map goes through one too, so it's
+/// never seen half written.
+pub(crate) fn partial_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".partial");
+    PathBuf::from(path)
+}
+
+/// Locks `<id_map>.lock` until the returned file is dropped, under
+/// `--coalesce-id-map-writes-with-lock`. The lock is advisory, and taken
+/// on a file of its own since the map is replaced on every write.
+fn lock_id_map(id_map: &Path) -> Result<File> {
+    let mut path = id_map.as_os_str().to_owned();
+    path.push(".lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Turns snapshots into rows and writes them out, holding everything a run
+/// accumulates along the way.
+pub struct Collector<'a> {
+    args: &'a Args,
+    columns: Columns,
+    filter: StationFilter,
+    schema: SchemaRef,
+    /// The output file until the first row group, when `writer` is created
+    /// so `--compression auto` has rows to try codecs on.
+    pending: Option<File>,
+    writer: Option<Sink>,
+    /// Another handle on the output file, to fsync it through under
+    /// `--durability`, since the writers keep theirs to themselves.
+    synced: Option<File>,
+    /// Rows in the row groups written so far.
+    written_rows: usize,
+    builders: Builders,
+    /// How many files the input glob matched, until the builders are sized
+    /// from it and the first snapshot.
+    input_files: Option<usize>,
+    transforms: Vec<(Box<dyn RowTransform>, RowBuilder)>,
+    duckdb: Option<DuckDbSink>,
+    duckdb_rows: Vec<Row>,
+    reservoir: Option<Reservoir>,
+    index: Option<RowGroupIndex>,
+    id_legend: HashMap<String, u16>,
+    id_counter: u16,
+    /// Minutes already ingested, so a snapshot saved twice by the downloader
+    /// doesn't produce duplicate (station, time) rows.
+    seen_minutes: HashSet<i64>,
+    station_counts: StationCounts,
+    /// Where the existing output's rows are cut off under `--window-days`,
+    /// until they are carried into the new one.
+    sliding: Option<SlidingWindow>,
+    /// Each station's last kept row, under `--dedup-window`.
+    last_kept: HashMap<u16, Row>,
+    rolling: Option<RollingOccupancy>,
+    /// The `--row-group-by` window of the last snapshot.
+    window: Option<i64>,
+    /// Snapshots before this time, in milliseconds, are skipped.
+    min_time: Option<i64>,
+    rewritten_ids: HashSet<String>,
+    timings: Vec<FileTiming>,
+    stats: Stats,
+    occupancy: Option<HashMap<u16, Occupancy>>,
+    skew: Option<SkewReport>,
+    feeds: Option<FeedOutputs<'a>>,
+    warnings: Warnings,
+    profiler: Profiler,
+    summary: Summary,
+}
+
+/// Configures a [`Collector`] before its output is created.
+pub struct CollectorBuilder<'a> {
+    args: &'a Args,
+    transforms: Vec<Box<dyn RowTransform>>,
+}
+
+impl<'a> CollectorBuilder<'a> {
+    /// Adds a transform's columns after the built-in ones, and after those
+    /// of any transform added before it.
+    pub fn transform(mut self, transform: impl RowTransform + 'static) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    pub fn build(mut self) -> Result<Collector<'a>> {
+        if !self.args.split_vehicle_availability_by_type {
+            return Collector::new(self.args, self.transforms);
+        }
+        // Before the discovery pass, which would otherwise be wasted.
+        check_overwrite(self.args)?;
+        let vehicle_types = vehicle_types::discover(self.args)?;
+        info!(?vehicle_types, "found vehicle types");
+        // Built in, so they come before any other transform's columns.
+        self.transforms
+            .insert(0, Box::new(VehicleTypeCounts::new(vehicle_types.clone())));
+        let mut collector = Collector::new(self.args, self.transforms)?;
+        collector.summary.vehicle_types = vehicle_types;
+        Ok(collector)
+    }
+
+    /// Builds the collector and runs it over every input snapshot.
+    pub fn collect(self) -> Result<Summary> {
+        let args = self.args;
+        let mut collector = self.build()?;
+        if let Err(err) = for_each_feed(args, |name, input| match input {
+            Input::StationStatus(snapshot) => collector.process(name, snapshot),
+            Input::Other(snapshot) => collector.process_other_feed(name, snapshot),
+        }) {
+            collector.abandon();
+            return Err(err);
+        }
+        collector.finish()
+    }
+}
+
+/// Collects the inputs `args` describes with only the built-in columns.
+pub fn collect(args: &Args) -> Result<Summary> {
+    Collector::builder(args).collect()
+}
+
+impl<'a> Collector<'a> {
+    pub fn builder(args: &'a Args) -> CollectorBuilder<'a> {
+        CollectorBuilder {
+            args,
+            transforms: Vec::new(),
+        }
+    }
+
+    fn new(args: &'a Args, transforms: Vec<Box<dyn RowTransform>>) -> Result<Self> {
+        check_overwrite(args)?;
+        check_schema(&args.columns())?;
+        if args.station_id_type == StationIdType::Dictionary {
+            // Both depend on integer ids that mean the same thing across runs.
+            if args.reuse_id_map {
+                return Err("--reuse-id-map needs --station-id-type mapped-u16".into());
+            }
+            if args.append_to_duckdb.is_some() {
+                return Err("--append-to-duckdb needs --station-id-type mapped-u16".into());
+            }
+            if args.emit_index.is_some() {
+                return Err("--emit-index needs --station-id-type mapped-u16".into());
+            }
+        }
+        if args.emit_index.is_some() && args.format != OutputFormat::Parquet {
+            return Err("--emit-index needs --format parquet".into());
+        }
+        if args.durability == Durability::Batch && args.format != OutputFormat::Parquet {
+            return Err("--durability batch needs --format parquet".into());
+        }
+        if args.coalesce_id_map_writes_with_lock
+            && args.station_id_type == StationIdType::Dictionary
+        {
+            return Err(
+                "--coalesce-id-map-writes-with-lock needs --station-id-type mapped-u16".into(),
+            );
+        }
+        if args.window_days.is_some() {
+            if args.station_id_type == StationIdType::Dictionary {
+                return Err("--window-days needs --station-id-type mapped-u16".into());
+            }
+            if args.format != OutputFormat::Parquet {
+                return Err("--window-days needs --format parquet".into());
+            }
+        }
+        if args.raw {
+            if !transforms.is_empty() {
+                return Err("--raw stores no computed columns, so it takes no transforms".into());
+            }
+            if args.enforce_monotonic_time == Some(MonotonicTime::Clamp) {
+                return Err(
+                    "--raw stores times as reported; use --enforce-monotonic-time drop".into(),
+                );
+            }
+        }
+        let filter = StationFilter::from_args(args)?;
+        let duckdb = match &args.append_to_duckdb {
+            Some(path) => Some(DuckDbSink::open(path, args.columns())?),
+            None => None,
+        };
+
+        let mut last_run = match &args.manifest {
+            Some(manifest) if args.since_last_run => last_run_max_time(manifest)?,
+            _ => None,
+        };
+        if let Some(stored) = stored_schema_version(&args.output) {
+            if stored.as_deref() != Some(SCHEMA_VERSION) {
+                info!(
+                    output = %args.output.display(),
+                    stored = stored.as_deref().unwrap_or("none"),
+                    current = SCHEMA_VERSION,
+                    "existing output has a different schema version, reprocessing every snapshot"
+                );
+                last_run = None;
+            }
+        }
+        // Both bounds are on the rounded snapshot time; `--from` includes its
+        // minute while the previous run's last minute was already collected.
+        let mut min_time = match (args.from, last_run) {
+            (Some(from), Some(last)) => Some(from.max(last + 1)),
+            (from, last) => from.or(last.map(|last| last + 1)),
+        };
+
+        // Ids from a previous run are kept as they are, including stations that
+        // no longer appear, and new stations are numbered after the highest one
+        // so an id is never handed out twice.
+        let id_legend: HashMap<String, u16> = if args.reuse_id_map && args.id_map.exists() {
+            read_id_map(&args.id_map)?
+        } else {
+            HashMap::new()
+        };
+        let id_counter: u16 = id_legend.values().copied().max().unwrap_or(0);
+
+        let columns = args.columns();
+        let input_files = match (args.builder_capacity, &args.input_tar) {
+            (None, None) => Some(input_paths(args)?.len()),
+            _ => None,
+        };
+
+        let mut fields = schema(&columns).fields().to_vec();
+        let transforms: Vec<_> = transforms
+            .into_iter()
+            .map(|transform| {
+                let added = transform.fields();
+                let out = RowBuilder::new(&added);
+                fields.extend(added.into_iter().map(Arc::new));
+                (transform, out)
+            })
+            .collect();
+        rename(&mut fields, &args.rename)?;
+        let schema = Arc::new(Schema::new(fields));
+
+        let sliding = match args.window_days {
+            Some(days) => {
+                let times = crate::row::schema(&columns).index_of("times")?;
+                let sliding = SlidingWindow::new(args, days, &schema, times)?;
+                let after_latest = sliding.latest.map(|latest| latest + 1);
+                min_time = min_time.max(Some(sliding.start)).max(after_latest);
+                last_run = last_run.max(sliding.latest);
+                Some(sliding)
+            }
+            None => None,
+        };
+
+        let file = File::create(partial_path(&args.output))?;
+
+        Ok(Collector {
+            args,
+            columns,
+            filter,
+            schema,
+            synced: match args.durability {
+                Durability::None => None,
+                Durability::Batch | Durability::Close => Some(file.try_clone()?),
+            },
+            pending: Some(file),
+            writer: None,
+            written_rows: 0,
+            builders: Builders::with_capacity(&columns, args.builder_capacity.unwrap_or(0)),
+            input_files,
+            transforms,
+            duckdb,
+            duckdb_rows: Vec::new(),
+            reservoir: args
+                .sample_out
+                .as_ref()
+                .map(|_| Reservoir::new(args.sample_n, args.seed)),
+            index: args.emit_index.as_ref().map(|_| RowGroupIndex::default()),
+            id_legend,
+            id_counter,
+            seen_minutes: HashSet::new(),
+            station_counts: StationCounts::default(),
+            sliding,
+            last_kept: HashMap::new(),
+            rolling: args.rolling_window.map(|window| {
+                RollingOccupancy::new(window, args.rolling_max_gap, args.rolling_on_gap)
+            }),
+            window: None,
+            min_time,
+            rewritten_ids: HashSet::new(),
+            timings: Vec::new(),
+            stats: Stats::default(),
+            occupancy: args
+                .emit_empty_stations_report
+                .as_ref()
+                .map(|_| HashMap::new()),
+            skew: args
+                .report_skew
+                .as_ref()
+                .map(|_| SkewReport::new(args.skew_threshold)),
+            feeds: match &args.other_feeds_dir {
+                Some(dir) => Some(FeedOutputs::new(args, dir)?),
+                None => None,
+            },
+            warnings: Warnings::new(args.warnings_out.is_some()),
+            profiler: Profiler::new(args.profile),
+            summary: Summary {
+                max_time: last_run,
+                ..Summary::default()
+            },
+        })
+    }
+
+    /// Writes a snapshot of another feed out under `--other-feeds-dir`,
+    /// applying `--on-error` if it doesn't parse.
+    fn process_other_feed(&mut self, name: &str, snapshot: OtherFeed) -> Result<()> {
+        info!(file = name, feed = snapshot.feed, "processing");
+        let Some(feeds) = &mut self.feeds else {
+            return Ok(());
+        };
+        match feeds.write(&snapshot) {
+            Ok(()) => self.summary.other_feed_files += 1,
+            Err(err) if self.args.on_error == OnError::Fail => {
+                return Err(format!("{name}: {err}").into());
+            }
+            Err(err) => {
+                self.warnings.locate(name, None);
+                self.warnings
+                    .report(None, WarningKind::UnreadableSnapshot, err.to_string());
+                self.summary.skipped_files += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles one input; an error is a snapshot `--on-error` skipped.
+    pub fn process(&mut self, name: &str, snapshot: Result<Snapshot>) -> Result<()> {
+        info!(file = name, "processing");
+        self.warnings.locate(name, None);
+        let snapshot = match snapshot {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                self.warnings
+                    .report(None, WarningKind::UnreadableSnapshot, err.to_string());
+                self.summary.skipped_files += 1;
+                return Ok(());
+            }
+        };
+        let args = self.args;
+        let status = snapshot.status;
+        self.profiler
+            .add(Phase::Decompress, snapshot.timing.decompress_ms);
+        self.profiler.add(Phase::Parse, snapshot.timing.parse_ms);
+        self.timings.push(snapshot.timing);
+        if let (Some(skew), Some(modified)) = (&mut self.skew, snapshot.modified) {
+            skew.add(name, modified, status.last_updated);
+        }
+        if let Some(checksum) = snapshot.checksum {
+            self.summary.checksums.insert(name.to_string(), checksum);
+        }
+        let seconds = if args.raw {
+            status.last_updated
+        } else {
+            args.time_rounding.apply(status.last_updated)
+        };
+        let mut time = NaiveDateTime::from_timestamp_opt(seconds, 0).unwrap();
+        if self
+            .min_time
+            .is_some_and(|min_time| time.timestamp_millis() < min_time)
+        {
+            debug!(file = name, %time, "skipping snapshot from before the collected range");
+            self.summary.skipped_old_snapshots += 1;
+            return Ok(());
+        }
+        self.warnings.locate(name, Some(time.timestamp_millis()));
+        if let (Some(policy), Some(latest)) = (args.enforce_monotonic_time, self.summary.max_time) {
+            if time.timestamp_millis() < latest {
+                let latest = NaiveDateTime::from_timestamp_millis(latest).unwrap();
+                self.warnings.report(
+                    None,
+                    WarningKind::BackwardsTime,
+                    format!("{time} is before {latest}, applying {policy:?}"),
+                );
+                self.summary.backwards_snapshots += 1;
+                match policy {
+                    MonotonicTime::Drop => return Ok(()),
+                    MonotonicTime::Clamp => time = latest + Duration::minutes(1),
+                }
+            }
+        }
+        // Checked before deduplication, so a broken snapshot doesn't claim
+        // the minute from a good one.
+        let active = status
+            .data
+            .stations
+            .iter()
+            .filter(|station| station.station_status == "active")
+            .count();
+        if let Some(minimum) = self.min_stations() {
+            if active < minimum {
+                self.warnings.report(
+                    None,
+                    WarningKind::TooFewStations,
+                    format!("{active} active stations, fewer than {minimum}"),
+                );
+                self.summary.degenerate_snapshots += 1;
+                return Ok(());
+            }
+        }
+        if !self.seen_minutes.insert(time.timestamp_millis()) {
+            self.warnings.report(
+                None,
+                WarningKind::DuplicateMinute,
+                format!("{time} was already collected"),
+            );
+            self.summary.duplicate_snapshots += 1;
+            return Ok(());
+        }
+        self.station_counts.add(active);
+        if let Some(files) = self.input_files.take() {
+            let capacity = files.saturating_mul(active).min(args.row_group_size);
+            debug!(capacity, "sizing builders");
+            self.builders = Builders::with_capacity(&self.columns, capacity);
+        }
+        if let Some(window) = args.row_group_by {
+            let window = time
+                .timestamp_millis()
+                .div_euclid(window.num_milliseconds());
+            if self
+                .window
+                .replace(window)
+                .is_some_and(|last| last != window)
+                && self.builders.len() > 0
+            {
+                self.write_row_group()?;
+            }
+        }
+        let rewritten_ids = &mut self.rewritten_ids;
+        let inactive_stations = &mut self.summary.inactive_stations;
+        let filter = &self.filter;
+        let stations: Vec<Station> = status
+            .data
+            .stations
+            .into_iter()
+            .map(|mut station| {
+                if let Some(original) = station.strip_id_prefix(args.strip_id_prefix.as_deref()) {
+                    rewritten_ids.insert(original);
+                }
+                station
+            })
+            .filter(|station| {
+                let active = station.station_status == "active";
+                if !active {
+                    *inactive_stations
+                        .entry(station.station_status.clone())
+                        .or_default() += 1;
+                }
+                active
+            })
+            .filter(|station| filter.keeps(&station.station_id))
+            .collect();
+        self.summary.files += 1;
+        self.summary.max_time = self.summary.max_time.max(Some(time.timestamp_millis()));
+        let source_file = self.columns.source_file.then(|| name.to_string());
+        let (max_bikes, max_docks) = if args.raw {
+            (u16::MAX, u16::MAX)
+        } else {
+            (args.max_bikes, args.max_docks)
+        };
+
+        if args.coalesce_id_map_writes_with_lock {
+            let started = self.profiler.start();
+            self.assign_ids_locked(&stations)?;
+            self.profiler.stop(Phase::IdLookup, started);
+        }
+        for station in &stations {
+            let started = self.profiler.start();
+            let station_id = self.station_id(&station.station_id);
+            self.profiler.stop(Phase::IdLookup, started);
+            let (num_ebikes_disabled, num_classic_disabled) = if args.raw {
+                (None, None)
+            } else {
+                station.disabled_breakdown(&args.ebike_vehicle_types, max_bikes, &mut self.warnings)
+            };
+            let mut row = Row {
+                station_id,
+                num_bikes_available: station.count(
+                    "num_bikes_available",
+                    station.num_bikes_available,
+                    max_bikes,
+                    &mut self.warnings,
+                ),
+                num_ebikes_available: station.count(
+                    "num_ebikes_available",
+                    station.num_ebikes_available,
+                    max_bikes,
+                    &mut self.warnings,
+                ),
+                num_bikes_disabled: station.count(
+                    "num_bikes_disabled",
+                    station.num_bikes_disabled,
+                    max_bikes,
+                    &mut self.warnings,
+                ),
+                num_ebikes_disabled,
+                num_classic_disabled,
+                num_docks_available: station.count(
+                    "num_docks_available",
+                    station.num_docks_available,
+                    max_docks,
+                    &mut self.warnings,
+                ),
+                is_returning: station.is_returning(&mut self.warnings),
+                time: time.timestamp_millis(),
+                source_file: source_file.clone(),
+                last_reported: station.last_reported.and_then(epoch_millis),
+                eightd_has_available_keys: station.eightd_has_available_keys,
+                occupancy_rolling_mean: None,
+            };
+            if let Some(window) = args.dedup_window {
+                match self.last_kept.get(&station_id) {
+                    Some(kept)
+                        if row.same_values(kept)
+                            && (row.time - kept.time).abs() < window.num_milliseconds() =>
+                    {
+                        self.summary.near_duplicate_rows += 1;
+                        continue;
+                    }
+                    _ => {
+                        self.last_kept.insert(station_id, row.clone());
+                    }
+                }
+            }
+            self.summary.rows += 1;
+            if let Some(rolling) = &mut self.rolling {
+                row.occupancy_rolling_mean = rolling.add(
+                    station_id,
+                    row.time,
+                    row.num_bikes_available,
+                    row.num_docks_available,
+                );
+            }
+            let started = self.profiler.start();
+            for (transform, out) in &mut self.transforms {
+                transform.apply(station, time, out);
+            }
+            self.profiler.stop(Phase::Append, started);
+            self.append(row)?;
+        }
+        if let Some(duckdb) = &mut self.duckdb {
+            let started = self.profiler.start();
+            self.summary.duckdb_inserted += duckdb.insert(&self.duckdb_rows)?;
+            self.duckdb_rows.clear();
+            self.profiler.stop(Phase::Flush, started);
+        }
+        Ok(())
+    }
+
+    /// The fewest active stations a snapshot may have, the stricter of
+    /// `--min-stations` and `--min-stations-fraction`.
+    fn min_stations(&self) -> Option<usize> {
+        let relative = self
+            .args
+            .min_stations_fraction
+            .zip(self.station_counts.median())
+            .map(|(fraction, median)| (fraction * median as f64).ceil() as usize);
+        self.args.min_stations.max(relative)
+    }
+
+    /// Provider station ids in mapped id order, so id `n` is at `n - 1`.
+    fn station_names(&self) -> Vec<&str> {
+        let mut names = vec![""; usize::from(self.id_counter)];
+        for (name, id) in &self.id_legend {
+            names[usize::from(*id) - 1] = name;
+        }
+        names
+    }
+
+    /// The integer id for a provider station id, assigning the next one if
+    /// it hasn't been seen before.
+    fn station_id(&mut self, station_id: &str) -> u16 {
+        let id_counter = &mut self.id_counter;
+        *self
+            .id_legend
+            .entry(station_id.to_string())
+            .or_insert_with(|| {
+                *id_counter += 1;
+                *id_counter
+            })
+    }
+
+    fn append(&mut self, row: Row) -> Result<()> {
+        if let Some(reservoir) = &mut self.reservoir {
+            reservoir.offer(&row);
+        }
+        if let Some(index) = &mut self.index {
+            index.add_station(row.station_id);
+        }
+        let started = self.profiler.start();
+        self.builders.append(&row);
+        self.profiler.stop(Phase::Append, started);
+        self.stats.add(&row);
+        if let Some(occupancy) = &mut self.occupancy {
+            let station = occupancy.entry(row.station_id).or_default();
+            station.observations += 1;
+            // Nulls are unknown rather than empty, so they never count as occupied.
+            station.ever_occupied |= row.num_bikes_available.unwrap_or(0) > 0
+                || row.num_docks_available.unwrap_or(0) > 0;
+        }
+        if self.duckdb.is_some() {
+            self.duckdb_rows.push(row);
+        }
+        if let Some(limit) = self.args.arrow_memory_pool_limit {
+            let allocated = self.builders.allocated_bytes();
+            if allocated > limit {
+                return Err(format!(
+                    "memory limit exceeded, lower --row-group-size: the column builders hold {allocated} bytes, over --arrow-memory-pool-limit {limit}"
+                )
+                .into());
+            }
+        }
+        if self.builders.len() >= self.args.row_group_size {
+            self.write_row_group()?;
+        } else if self
+            .args
+            .max_memory
+            .is_some_and(|max_memory| self.builders.estimated_bytes() >= max_memory)
+        {
+            info!(
+                rows = self.builders.len(),
+                estimated_bytes = self.builders.estimated_bytes(),
+                "flushing early, over --max-memory"
+            );
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    /// The parquet writer, created on first use with the `--compression`
+    /// codec. Under `auto` the codec is picked from `sample`, the first row
+    /// group, falling back to snappy for an empty output.
+    fn writer(&mut self, sample: Option<&RecordBatch>) -> Result<&mut Sink> {
+        if let Some(file) = self.pending.take() {
+            self.writer = Some(match self.args.format {
+                OutputFormat::Json => Sink::Json(ArrayWriter::new(BufWriter::new(file))),
+                OutputFormat::Ndjson => {
+                    Sink::Ndjson(LineDelimitedWriter::new(BufWriter::new(file)))
+                }
+                OutputFormat::Parquet => {
+                    Sink::Parquet(Box::new(self.parquet_writer(file, sample)?))
+                }
+            });
+            let sliding = self.sliding.take().filter(|_| self.args.output.exists());
+            if let (Some(sliding), Some(Sink::Parquet(writer))) = (sliding, &mut self.writer) {
+                let stats = &mut self.stats;
+                let classic_bikes = self.columns.classic_bikes;
+                let (kept, dropped) =
+                    sliding.carry_over(&self.args.output, &self.schema, writer, |batch| {
+                        stats.add_batch(batch, classic_bikes)
+                    })?;
+                info!(
+                    kept,
+                    dropped, "carried over the existing output's rows in --window-days"
+                );
+                self.written_rows += kept;
+                self.summary.row_groups += writer.flushed_row_groups().len();
+                self.summary.window_kept_rows = kept;
+                self.summary.window_dropped_rows = dropped;
+            }
+        }
+        Ok(self
+            .writer
+            .as_mut()
+            .expect("writer is created before it is closed"))
+    }
+
+    fn parquet_writer(
+        &self,
+        file: File,
+        sample: Option<&RecordBatch>,
+    ) -> Result<ArrowWriter<File>> {
+        let codec = match (self.args.compression, sample) {
+            (Codec::Auto, Some(batch)) => pick_codec(
+                &self.schema,
+                batch,
+                self.args.parquet_version,
+                self.args.row_group_size,
+                self.args.compression_time_weight,
+            )?,
+            (Codec::Auto, None) => Codec::Snappy,
+            (codec, _) => codec,
+        };
+        let props = properties(
+            codec,
+            self.args.parquet_version,
+            self.args.row_group_size,
+            Some(SCHEMA_VERSION),
+        );
+        Ok(ArrowWriter::try_new(
+            file,
+            self.schema.clone(),
+            Some(props),
+        )?)
+    }
+
+    /// Writes the buffered rows out as a row group, then the id map, without
+    /// ending the run; for embedders checkpointing on their own schedule.
+    /// Once it returns, every id in the written row groups is in the id map.
+    /// The output only becomes readable at `finish`, which writes the
+    /// parquet footer and moves it into place.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.builders.len() > 0 {
+            self.write_row_group()?;
+        }
+        self.write_id_map()
+    }
+
+    /// Writes the buffered rows out as their own row group.
+    fn write_row_group(&mut self) -> Result<()> {
+        let started = self.profiler.start();
+        let station_names = self
+            .columns
+            .station_id_dictionary
+            .then(|| StringArray::from(self.station_names()));
+        let mut columns = self.builders.finish(station_names).columns().to_vec();
+        for (_, out) in &mut self.transforms {
+            columns.extend(out.finish());
+        }
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        if let Err(err) = self
+            .writer(Some(&batch))
+            .and_then(|sink| sink.write(&batch))
+        {
+            return Err(self.write_failed(err));
+        }
+        if self.args.durability == Durability::Batch {
+            if let Err(err) = self.sync() {
+                return Err(self.write_failed(err));
+            }
+        }
+        if let (Some(index), Some(Sink::Parquet(writer))) = (&mut self.index, &self.writer) {
+            let written = writer.flushed_row_groups().last();
+            index.add_row_group(written.expect("the batch was just written"));
+        }
+        self.written_rows += batch.num_rows();
+        self.summary.row_groups += 1;
+        if self.args.checkpoint_id_map {
+            self.write_id_map()?;
+        }
+        self.profiler.stop(Phase::Flush, started);
+        Ok(())
+    }
+
+    /// Fsyncs the output, if `--durability` asks for it at all.
+    fn sync(&mut self) -> Result<()> {
+        if let Some(file) = &self.synced {
+            file.sync_all()?;
+            self.summary.syncs += 1;
+        }
+        Ok(())
+    }
+
+    /// Closes what was written as best it can, so earlier row groups stay
+    /// readable in the `.partial` file, and describes the failure.
+    fn write_failed(&mut self, err: Box<dyn std::error::Error>) -> Box<dyn std::error::Error> {
+        if let Some(sink) = self.writer.take() {
+            if let Err(close_err) = sink.close(self.summary.row_groups == 0) {
+                warn!(error = %close_err, "couldn't close the partial output");
+            }
+        }
+        format!(
+            "writing {} failed after {} rows, leaving what was written in {}: {err}",
+            self.args.output.display(),
+            self.written_rows,
+            partial_path(&self.args.output).display()
+        )
+        .into()
+    }
+
+    /// Gives up on a run that failed before `finish`, removing the
+    /// `.partial` output if no row group got as far as it, as when
+    /// `--on-error fail` stops at a bad snapshot. One with rows in it is
+    /// left for `write_failed`'s message to point at.
+    fn abandon(mut self) {
+        if self.pending.take().is_some() {
+            drop(self.synced.take());
+            let partial = partial_path(&self.args.output);
+            if let Err(err) = fs::remove_file(&partial) {
+                warn!(file = %partial.display(), error = %err, "couldn't remove the empty partial output");
+            }
+        }
+    }
+
+    /// Numbers the stations not in the id map yet, with the map locked and
+    /// after merging in the ids other collectors sharing it have handed
+    /// out, and writes it back before unlocking.
+    fn assign_ids_locked(&mut self, stations: &[Station]) -> Result<()> {
+        if stations
+            .iter()
+            .all(|station| self.id_legend.contains_key(&station.station_id))
+        {
+            return Ok(());
+        }
+        let _lock = lock_id_map(&self.args.id_map)?;
+        self.merge_id_map()?;
+        for station in stations {
+            self.station_id(&station.station_id);
+        }
+        self.write_id_map_file()
+    }
+
+    /// Adds the stations in the id map on disk that another collector put
+    /// there, failing if one disagrees with an id handed out here.
+    fn merge_id_map(&mut self) -> Result<()> {
+        if !self.args.id_map.exists() {
+            return Ok(());
+        }
+        for (station_id, id) in read_id_map(&self.args.id_map)? {
+            let ours = *self.id_legend.entry(station_id.clone()).or_insert(id);
+            if ours != id {
+                return Err(format!(
+                    "station {station_id} is {id} in {} but {ours} here; it was numbered without the lock",
+                    self.args.id_map.display()
+                )
+                .into());
+            }
+            self.id_counter = self.id_counter.max(id);
+        }
+        Ok(())
+    }
+
+    fn write_id_map(&mut self) -> Result<()> {
+        if self.args.station_id_type == StationIdType::Dictionary {
+            return Ok(());
+        }
+        if !self.args.coalesce_id_map_writes_with_lock {
+            return self.write_id_map_file();
+        }
+        let _lock = lock_id_map(&self.args.id_map)?;
+        self.merge_id_map()?;
+        self.write_id_map_file()
+    }
+
+    fn write_id_map_file(&self) -> Result<()> {
+        // Serialized straight into the file, so a large legend is never held
+        // in memory a second time as one string.
+        let partial = partial_path(&self.args.id_map);
+        let mut file = BufWriter::new(File::create(&partial)?);
+        if self.args.pretty_id_map {
+            let sorted = SortedIdMap(
+                self.id_legend
+                    .iter()
+                    .map(|(k, v)| (*v, k.as_str()))
+                    .collect(),
+            );
+            serde_json::to_writer_pretty(&mut file, &sorted)?;
+        } else {
+            serde_json::to_writer(&mut file, &self.id_legend)?;
+        }
+        file.flush()?;
+        drop(file);
+        fs::rename(partial, &self.args.id_map)?;
+        Ok(())
+    }
+
+    /// Writes the remaining rows and every sidecar output, the id map and
+    /// then the manifest last, returning the run's summary.
+    pub fn finish(mut self) -> Result<Summary> {
+        let args = self.args;
+        if self.builders.len() > 0 {
+            self.write_row_group()?;
+        }
+
+        let started = self.profiler.start();
+        self.writer(None)?;
+        let mut sink = self.writer.take().unwrap();
+        if let Sink::Parquet(writer) = &mut sink {
+            for kv in self.stats.key_values() {
+                writer.append_key_value_metadata(kv);
+            }
+        }
+        // writer must be closed to write footer
+        if let Err(err) = sink.close(self.summary.row_groups == 0) {
+            return Err(self.write_failed(err));
+        }
+        self.sync()?;
+        fs::rename(partial_path(&args.output), &args.output)?;
+        self.profiler.stop(Phase::Flush, started);
+
+        if let (Some(reservoir), Some(path)) = (&self.reservoir, &args.sample_out) {
+            let station_names = self
+                .columns
+                .station_id_dictionary
+                .then(|| self.station_names());
+            reservoir.write(path, &self.columns, station_names.as_deref())?;
+        }
+        if let (Some(skew), Some(path)) = (&self.skew, &args.report_skew) {
+            skew.write(path)?;
+        }
+        if let (Some(index), Some(path)) = (&self.index, &args.emit_index) {
+            index.write(path)?;
+        }
+        if let Some(path) = &args.report_station_counts {
+            self.station_counts.write(path)?;
+        }
+        if let Some(feeds) = self.feeds.take() {
+            self.summary.other_feed_rows = feeds.finish()?;
+        }
+
+        if let Some(path) = &args.warnings_out {
+            self.warnings.write(path)?;
+        }
+        if let Some(path) = &args.columns_metadata {
+            let columns = describe(&self.schema, &self.columns, &args.rename);
+            serde_json::to_writer_pretty(File::create(path)?, &columns)?;
+        }
+
+        if let (Some(occupancy), Some(path)) = (&self.occupancy, &args.emit_empty_stations_report) {
+            let names: HashMap<u16, &str> = self
+                .id_legend
+                .iter()
+                .map(|(name, id)| (*id, name.as_str()))
+                .collect();
+            let mut empty: Vec<EmptyStation> = occupancy
+                .iter()
+                .filter(|(_, station)| !station.ever_occupied)
+                .map(|(id, station)| EmptyStation {
+                    station_id: names[id],
+                    id: *id,
+                    observations: station.observations,
+                })
+                .collect();
+            empty.sort_by_key(|station| station.id);
+            serde_json::to_writer_pretty(File::create(path)?, &empty)?;
+        }
+
+        let started = self.profiler.start();
+        self.write_id_map()?;
+        self.profiler.stop(Phase::Flush, started);
+
+        let mut summary = self.summary;
+        summary.stations = self.id_legend.len();
+        summary.rewritten_ids = self.rewritten_ids.len();
+        summary.warnings = self.warnings.count();
+        self.timings
+            .sort_by(|a, b| b.total_ms().total_cmp(&a.total_ms()));
+        self.timings.truncate(args.slowest);
+        summary.slowest_files = self.timings;
+        summary.profile = self.profiler.finish();
+        if let Some(manifest) = &args.manifest {
+            serde_json::to_writer_pretty(File::create(manifest)?, &summary)?;
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::AsArray;
+    use arrow::datatypes::{DataType, Field, Int64Type};
+    use arrow_array::builder::{Int64Builder, UInt16Builder};
+    use chrono::Timelike;
+    use clap::Parser;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde_json::json;
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::snapshot::for_each_snapshot;
+    use crate::test_fixtures::{
+        document, footer_value, metadata, read_json, read_parquet, station, string_column, times,
+        u16_column, write_gz, TempDir, T0,
+    };
+
+    fn run(dir: &TempDir, extra: &[&str]) -> Summary {
+        collect(&Args::parse_from(dir.collect_argv(extra))).unwrap()
+    }
+
+    fn id_map(dir: &TempDir) -> HashMap<String, u16> {
+        serde_json::from_slice(&fs::read(dir.id_map()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn vehicle_types_available_splits_disabled_bikes() {
+        let dir = TempDir::new();
+        let mut broken_down = station("A", 10, 4, 5);
+        broken_down["num_bikes_disabled"] = json!(5);
+        broken_down["vehicle_types_available"] = json!([
+            {"vehicle_type_id": "1", "count": 6, "num_bikes_disabled": 3},
+            {"vehicle_type_id": "2", "count": 4, "num_bikes_disabled": 2},
+        ]);
+        let mut combined = station("B", 10, 4, 5);
+        combined["num_bikes_disabled"] = json!(7);
+        dir.snapshot(T0, vec![broken_down, combined]);
+        run(&dir, &[]);
+
+        let batch = read_parquet(&dir.output());
+        assert_eq!(
+            u16_column(&batch, "num_ebikes_available"),
+            [Some(4), Some(4)]
+        );
+        assert_eq!(u16_column(&batch, "num_bikes_disabled"), [Some(5), Some(7)]);
+        assert_eq!(u16_column(&batch, "num_ebikes_disabled"), [Some(2), None]);
+        assert_eq!(
+            u16_column(&batch, "num_classic_disabled"),
+            [Some(3), Some(7)]
+        );
+    }
+
+    #[test]
+    fn duplicate_snapshot_is_ingested_once() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 3, 1, 5)]);
+        write_gz(
+            &dir.join("station_status/copy.json.gz"),
+            &document(T0, vec![station("A", 3, 1, 5)]),
+        );
+        let summary = run(&dir, &[]);
+        assert_eq!(summary.files, 1);
+        assert_eq!(summary.duplicate_snapshots, 1);
+        assert_eq!(read_parquet(&dir.output()).num_rows(), 1);
+    }
+
+    #[test]
+    fn pretty_id_map_is_in_id_order() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("Z", 1, 0, 1), station("A", 1, 0, 1)]);
+        dir.snapshot(T0 + 60, vec![station("M", 1, 0, 1), station("A", 1, 0, 1)]);
+        run(&dir, &["--pretty-id-map"]);
+        let id_map = fs::read_to_string(dir.id_map()).unwrap();
+        assert_eq!(id_map, "{\n  \"Z\": 1,\n  \"A\": 2,\n  \"M\": 3\n}");
+    }
+
+    #[test]
+    fn slowest_files_are_listed_slowest_first() {
+        let dir = TempDir::new();
+        for minute in 0..3 {
+            dir.snapshot(
+                T0 + minute * 60,
+                vec![station("A", 1, 0, 1), station("B", 1, 0, 1)],
+            );
+        }
+        let summary = run(&dir, &["--slowest", "2"]);
+        assert_eq!(summary.slowest_files.len(), 2);
+        for timing in &summary.slowest_files {
+            assert!(timing.file.ends_with(".json.gz"), "{}", timing.file);
+            assert_eq!(timing.stations, 2);
+            assert!(timing.decompress_ms >= 0.0 && timing.parse_ms >= 0.0);
+        }
+        let [slowest, next] = &summary.slowest_files[..] else {
+            unreachable!()
+        };
+        assert!(slowest.total_ms() >= next.total_ms());
+    }
+
+    #[test]
+    fn on_error_decides_whether_a_corrupt_snapshot_aborts() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 3, 1, 5)]);
+        fs::write(
+            dir.join("station_status/corrupt.json.gz"),
+            "not gzip or json",
+        )
+        .unwrap();
+
+        let args = Args::parse_from(dir.collect_argv(&["--on-error", "fail"]));
+        let err = collect(&args).unwrap_err().to_string();
+        assert!(err.contains("corrupt.json.gz"), "{err}");
+        assert!(!dir.output().exists());
+        assert!(!partial_path(&dir.output()).exists());
+
+        let summary = run(&dir, &["--on-error", "skip"]);
+        assert_eq!(summary.skipped_files, 1);
+        assert_eq!(summary.rows, 1);
+        assert_eq!(read_parquet(&dir.output()).num_rows(), 1);
+    }
+
+    #[test]
+    fn with_source_names_each_rows_snapshot() {
+        let dir = TempDir::new();
+        let first = dir.snapshot(T0, vec![station("A", 1, 0, 1), station("B", 1, 0, 1)]);
+        let second = dir.snapshot(T0 + 60, vec![station("A", 1, 0, 1)]);
+        run(&dir, &["--with-source"]);
+        let (first, second) = (first.display().to_string(), second.display().to_string());
+        assert_eq!(
+            string_column(&read_parquet(&dir.output()), "source_file"),
+            [first.as_str(), &first, &second]
+        );
+    }
+
+    #[test]
+    fn checksums_are_the_files_sha256() {
+        use sha2::{Digest, Sha256};
+
+        let dir = TempDir::new();
+        let first = dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let second = dir.snapshot(T0 + 60, vec![station("A", 2, 0, 0)]);
+        let checksums = run(&dir, &["--checksum"]).checksums;
+        assert_eq!(
+            run(&dir, &["--checksum", "--overwrite"]).checksums,
+            checksums
+        );
+        for path in [first, second] {
+            let expected = format!("{:x}", Sha256::digest(fs::read(&path).unwrap()));
+            assert_eq!(checksums[&path.display().to_string()], expected);
+        }
+    }
+
+    #[test]
+    fn duckdb_skips_rows_it_already_has() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1), station("B", 1, 0, 1)]);
+        dir.snapshot(T0 + 60, vec![station("A", 2, 0, 0), station("B", 1, 0, 1)]);
+        let duckdb = dir.join("status.duckdb").display().to_string();
+        let args = [
+            "--append-to-duckdb",
+            &duckdb,
+            "--reuse-id-map",
+            "--overwrite",
+        ];
+        assert_eq!(run(&dir, &args).duckdb_inserted, 4);
+        assert_eq!(run(&dir, &args).duckdb_inserted, 0);
+        let rows: usize = duckdb::Connection::open(&duckdb)
+            .unwrap()
+            .query_row("SELECT count(*) FROM station_status", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 4);
+    }
+
+    #[test]
+    fn stations_allowlist_and_blocklist() {
+        let dir = TempDir::new();
+        let ids = ["A", "B", "C", "D", "E"];
+        dir.snapshot(T0, ids.iter().map(|id| station(id, 1, 0, 1)).collect());
+        fs::write(dir.join("allow.txt"), "A\n\nC\nE\n").unwrap();
+        let allow = dir.join("allow.txt").display().to_string();
+
+        run(&dir, &["--stations", &allow]);
+        assert_eq!(
+            id_map(&dir),
+            HashMap::from([("A".into(), 1), ("C".into(), 2), ("E".into(), 3)])
+        );
+        assert_eq!(read_parquet(&dir.output()).num_rows(), 3);
+
+        run(
+            &dir,
+            &[
+                "--stations",
+                "A,C,E",
+                "--exclude-stations",
+                "C",
+                "--overwrite",
+            ],
+        );
+        assert_eq!(
+            id_map(&dir),
+            HashMap::from([("A".into(), 1), ("E".into(), 2)])
+        );
+    }
+
+    #[test]
+    fn reuse_id_map_keeps_ids_and_numbers_new_stations_after_them() {
+        let dir = TempDir::new();
+        let first = dir.snapshot(
+            T0,
+            vec![
+                station("A", 1, 0, 1),
+                station("B", 1, 0, 1),
+                station("C", 1, 0, 1),
+            ],
+        );
+        run(&dir, &["--reuse-id-map"]);
+        let ids = HashMap::from([("A".into(), 1), ("B".into(), 2), ("C".into(), 3)]);
+        assert_eq!(id_map(&dir), ids);
+
+        fs::remove_file(first).unwrap();
+        dir.snapshot(
+            T0 + 60,
+            vec![
+                station("D", 1, 0, 1),
+                station("B", 1, 0, 1),
+                station("A", 1, 0, 1),
+            ],
+        );
+        let summary = run(&dir, &["--reuse-id-map", "--overwrite"]);
+        let mut ids = ids;
+        ids.insert("D".into(), 4);
+        assert_eq!(id_map(&dir), ids);
+        assert_eq!(summary.stations, 4);
+        assert_eq!(
+            u16_column(&read_parquet(&dir.output()), "station_ids"),
+            [Some(4), Some(2), Some(1)]
+        );
+    }
+
+    #[test]
+    fn max_memory_flushes_early() {
+        let dir = TempDir::new();
+        for minute in 0..4 {
+            dir.snapshot(
+                T0 + minute * 60,
+                vec![station("A", 1, 0, 1), station("B", 1, 0, 1)],
+            );
+        }
+        // A few rows' worth.
+        let summary = run(&dir, &["--max-memory", "64"]);
+        let footer = metadata(&dir.output());
+        assert_eq!(footer.num_row_groups(), summary.row_groups);
+        assert!(footer.num_row_groups() > 1, "{}", footer.num_row_groups());
+        assert_eq!(footer.file_metadata().num_rows(), 8);
+    }
+
+    #[test]
+    fn strip_id_prefix_normalizes_the_id_map() {
+        let dir = TempDir::new();
+        dir.snapshot(
+            T0,
+            vec![station("bcycle_madison_1", 1, 0, 1), station("2", 1, 0, 1)],
+        );
+        dir.snapshot(
+            T0 + 60,
+            vec![station("bcycle_madison_1", 1, 0, 1), station("1", 1, 0, 1)],
+        );
+        let summary = run(&dir, &["--strip-id-prefix", "bcycle_madison_"]);
+        assert_eq!(
+            id_map(&dir),
+            HashMap::from([("1".into(), 1), ("2".into(), 2)])
+        );
+        assert_eq!(summary.rewritten_ids, 1);
+        // Both spellings of station 1 land on one id.
+        assert_eq!(
+            u16_column(&read_parquet(&dir.output()), "station_ids"),
+            [Some(1), Some(2), Some(1), Some(1)]
+        );
+    }
+
+    #[test]
+    fn older_schema_version_reprocesses_since_last_run() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let manifest = dir.join("manifest.json").display().to_string();
+        run(&dir, &["--manifest", &manifest]);
+        let since_last_run = ["--manifest", &manifest, "--since-last-run", "--overwrite"];
+        assert_eq!(run(&dir, &since_last_run).skipped_old_snapshots, 1);
+
+        // An output from before `schema_version` was stamped.
+        let batch = RecordBatch::try_from_iter([(
+            "station_ids",
+            Arc::new(arrow_array::UInt16Array::from(vec![1])) as arrow_array::ArrayRef,
+        )])
+        .unwrap();
+        let mut writer =
+            ArrowWriter::try_new(File::create(dir.output()).unwrap(), batch.schema(), None)
+                .unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        let summary = run(&dir, &since_last_run);
+        assert_eq!(summary.skipped_old_snapshots, 0);
+        assert_eq!(summary.rows, 1);
+    }
+
+    #[test]
+    fn footer_stats_cover_every_row() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 3, 0, 1), station("B", 4, 1, 1)]);
+        dir.snapshot(T0 + 60, vec![station("A", 5, 2, 1)]);
+        run(&dir, &[]);
+        let stat = |key: &str| footer_value(&dir.output(), &format!("stats.{key}"));
+        assert_eq!(stat("rows").as_deref(), Some("3"));
+        assert_eq!(stat("stations").as_deref(), Some("2"));
+        assert_eq!(stat("num_bikes_available.min").as_deref(), Some("3"));
+        assert_eq!(stat("num_bikes_available.max").as_deref(), Some("5"));
+        assert_eq!(stat("num_bikes_available.mean").as_deref(), Some("4.000"));
+    }
+
+    #[test]
+    fn sample_and_duckdb_follow_bike_split() {
+        for (split, column, bikes) in [
+            ("classic", "num_classic_bikes_available", 3),
+            ("total", "num_bikes_available", 5),
+        ] {
+            let dir = TempDir::new();
+            dir.snapshot(T0, vec![station("A", 5, 2, 1)]);
+            let sample = dir.join("sample.jsonl");
+            let duckdb = dir.join("status.duckdb").display().to_string();
+            run(
+                &dir,
+                &[
+                    "--bike-split",
+                    split,
+                    "--sample-out",
+                    &sample.display().to_string(),
+                    "--append-to-duckdb",
+                    &duckdb,
+                ],
+            );
+            let row: serde_json::Value =
+                serde_json::from_str(fs::read_to_string(&sample).unwrap().trim()).unwrap();
+            assert_eq!(row[column], bikes, "{split}");
+            let other = if split == "total" {
+                "num_classic_bikes_available"
+            } else {
+                "num_bikes_available"
+            };
+            assert!(row.get(other).is_none(), "{split}");
+            let stored: u16 = duckdb::Connection::open(&duckdb)
+                .unwrap()
+                .query_row(&format!("SELECT {column} FROM station_status"), [], |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            assert_eq!(stored, bikes, "{split}");
+        }
+    }
+
+    #[test]
+    fn since_last_run_skips_snapshots_up_to_the_manifests_max_time() {
+        let dir = TempDir::new();
+        let manifest = dir.join("manifest.json").display().to_string();
+        let since_last_run = ["--manifest", &manifest, "--since-last-run", "--overwrite"];
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        dir.snapshot(T0 + 60, vec![station("A", 2, 0, 0)]);
+        // Without a manifest yet, everything is new.
+        let first = run(&dir, &since_last_run);
+        assert_eq!((first.skipped_old_snapshots, first.rows), (0, 2));
+
+        dir.snapshot(T0 + 120, vec![station("A", 3, 0, 0)]);
+        let second = run(&dir, &since_last_run);
+        assert_eq!((second.skipped_old_snapshots, second.rows), (2, 1));
+    }
+
+    #[test]
+    fn large_id_legend_round_trips() {
+        let dir = TempDir::new();
+        let ids: Vec<String> = (0..5000).map(|n| format!("station-{n}")).collect();
+        dir.snapshot(T0, ids.iter().map(|id| station(id, 1, 0, 1)).collect());
+        assert_eq!(run(&dir, &[]).rows, 5000);
+        let map = id_map(&dir);
+        assert_eq!(map.len(), 5000);
+        let mut numbers: Vec<u16> = map.values().copied().collect();
+        numbers.sort_unstable();
+        assert_eq!(numbers, (1..=5000).collect::<Vec<u16>>());
+        assert!(ids.iter().all(|id| map.contains_key(id)));
+    }
+
+    #[test]
+    fn second_run_without_overwrite_leaves_the_outputs_alone() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let manifest = dir.join("manifest.json");
+        let manifest_arg = manifest.display().to_string();
+        run(&dir, &["--manifest", &manifest_arg]);
+        let outputs = [dir.output(), dir.id_map(), manifest];
+        let before: Vec<Vec<u8>> = outputs.iter().map(|path| fs::read(path).unwrap()).collect();
+
+        dir.snapshot(T0 + 60, vec![station("B", 1, 0, 1)]);
+        let args = Args::parse_from(dir.collect_argv(&["--manifest", &manifest_arg]));
+        let err = collect(&args).unwrap_err().to_string();
+        assert!(err.contains("pass --overwrite"), "{err}");
+        for (path, before) in outputs.iter().zip(before) {
+            assert_eq!(fs::read(path).unwrap(), before, "{}", path.display());
+        }
+    }
+
+    struct MinuteAndEbikes;
+
+    impl RowTransform for MinuteAndEbikes {
+        fn fields(&self) -> Vec<Field> {
+            vec![
+                Field::new("minute", DataType::UInt16, false),
+                Field::new("ebikes_seen", DataType::Int64, false),
+            ]
+        }
+
+        fn apply(&self, station: &Station, time: NaiveDateTime, out: &mut RowBuilder) {
+            out.column::<UInt16Builder>(0)
+                .append_value(time.minute() as u16);
+            out.column::<Int64Builder>(1)
+                .append_value(station.num_ebikes_available);
+        }
+    }
+
+    #[test]
+    fn transform_columns_follow_the_built_in_ones() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 3, 1, 1)]);
+        dir.snapshot(T0 + 60, vec![station("A", 3, 2, 1)]);
+        let args = Args::parse_from(dir.collect_argv(&[]));
+        Collector::builder(&args)
+            .transform(MinuteAndEbikes)
+            .collect()
+            .unwrap();
+        let batch = read_parquet(&dir.output());
+        let schema = batch.schema();
+        let names: Vec<&str> = schema
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect();
+        assert_eq!(names[names.len() - 2..], ["minute", "ebikes_seen"]);
+        assert_eq!(u16_column(&batch, "minute"), [Some(54), Some(55)]);
+        let ebikes = batch
+            .column_by_name("ebikes_seen")
+            .unwrap()
+            .as_primitive::<Int64Type>();
+        assert_eq!(ebikes.values(), &[1, 2]);
+    }
+
+    #[test]
+    fn gbfs_schema_flags_an_out_of_range_count() {
+        let dir = TempDir::new();
+        let schema = dir.join("station_status.schema.json");
+        fs::write(
+            &schema,
+            json!({
+                "type": "object",
+                "required": ["data"],
+                "properties": {"data": {"properties": {"stations": {"items": {
+                    "properties": {"num_bikes_available": {"type": "integer", "minimum": 0}}
+                }}}}}
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let schema = schema.display().to_string();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        dir.snapshot(T0 + 60, vec![station("A", -5, 0, 1)]);
+
+        let summary = run(&dir, &["--gbfs-schema", &schema]);
+        assert_eq!((summary.skipped_files, summary.rows), (1, 1));
+
+        let args = Args::parse_from(dir.collect_argv(&[
+            "--gbfs-schema",
+            &schema,
+            "--on-error",
+            "fail",
+            "--overwrite",
+        ]));
+        let err = collect(&args).unwrap_err().to_string();
+        assert!(err.contains("violation(s) of --gbfs-schema"), "{err}");
+    }
+
+    #[test]
+    fn sentinel_counts_are_stored_as_null() {
+        let dir = TempDir::new();
+        dir.snapshot(
+            T0,
+            vec![
+                station("A", 65000, 0, 1),
+                station("B", -1, 0, -1),
+                station("C", 3, 0, 20),
+            ],
+        );
+        run(&dir, &["--bike-split", "total", "--max-docks", "10"]);
+        let batch = read_parquet(&dir.output());
+        assert_eq!(
+            u16_column(&batch, "num_bikes_available"),
+            [None, None, Some(3)]
+        );
+        assert_eq!(
+            u16_column(&batch, "num_docks_available"),
+            [Some(1), None, None]
+        );
+    }
+
+    #[test]
+    fn empty_stations_report_lists_only_stations_never_occupied() {
+        let dir = TempDir::new();
+        dir.snapshot(
+            T0,
+            vec![station("empty", 0, 0, 0), station("busy", 0, 0, 3)],
+        );
+        dir.snapshot(
+            T0 + 60,
+            vec![station("empty", 0, 0, 0), station("busy", 2, 0, 1)],
+        );
+        let report = dir.join("empty_stations.json");
+        run(
+            &dir,
+            &[
+                "--emit-empty-stations-report",
+                &report.display().to_string(),
+            ],
+        );
+        assert_eq!(
+            read_json(&report),
+            json!([{"station_id": "empty", "id": id_map(&dir)["empty"], "observations": 2}])
+        );
+    }
+
+    #[test]
+    fn codec_is_recorded_and_the_file_reads_back() {
+        use parquet::basic::Compression;
+
+        for codec in ["snappy", "zstd", "auto"] {
+            let dir = TempDir::new();
+            dir.snapshot(T0, vec![station("A", 3, 1, 5), station("B", 2, 0, 4)]);
+            dir.snapshot(T0 + 60, vec![station("A", 4, 1, 4)]);
+            run(&dir, &["--compression", codec]);
+            let recorded = footer_value(&dir.output(), "compression").unwrap();
+            let expected = match codec {
+                "auto" => {
+                    assert!(
+                        ["snappy", "zstd"].contains(&recorded.as_str()),
+                        "{recorded}"
+                    );
+                    recorded.as_str()
+                }
+                codec => {
+                    assert_eq!(recorded, codec);
+                    codec
+                }
+            };
+            let written = metadata(&dir.output()).row_group(0).column(0).compression();
+            assert_eq!(
+                matches!(written, Compression::ZSTD(_)),
+                expected == "zstd",
+                "{codec}: {written:?}"
+            );
+            assert_eq!(
+                matches!(written, Compression::SNAPPY),
+                expected == "snappy",
+                "{codec}: {written:?}"
+            );
+            assert_eq!(
+                u16_column(&read_parquet(&dir.output()), "num_classic_bikes_available"),
+                [Some(2), Some(2), Some(3)],
+                "{codec}"
+            );
+        }
+    }
+
+    #[test]
+    fn resume_from_file_skips_up_to_and_including_it() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let resume = dir.snapshot(T0 + 60, vec![station("A", 2, 0, 1)]);
+        dir.snapshot(T0 + 120, vec![station("A", 3, 0, 1)]);
+        let summary = run(&dir, &["--resume-from-file", &resume.display().to_string()]);
+        assert_eq!((summary.files, summary.rows), (1, 1));
+        assert_eq!(
+            u16_column(&read_parquet(&dir.output()), "num_classic_bikes_available"),
+            [Some(3)]
+        );
+
+        let missing = dir.join("station_status/missing.json.gz");
+        let args = Args::parse_from(dir.collect_argv(&[
+            "--resume-from-file",
+            &missing.display().to_string(),
+            "--overwrite",
+        ]));
+        let err = collect(&args).unwrap_err().to_string();
+        assert!(err.contains("isn't matched by --input"), "{err}");
+    }
+
+    #[test]
+    fn skew_report_flags_files_written_long_after_last_updated() {
+        let dir = TempDir::new();
+        // Written just now, years after `last_updated`.
+        let stale = dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        dir.snapshot(now, vec![station("A", 2, 0, 1)]);
+        let report = dir.join("skew.json");
+        run(&dir, &["--report-skew", &report.display().to_string()]);
+        let report = read_json(&report);
+        assert_eq!(report["files"], 2);
+        let flagged = report["flagged"].as_array().unwrap();
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0]["file"], stale.display().to_string());
+        assert_eq!(flagged[0]["last_updated"], T0);
+    }
+
+    #[test]
+    fn json_output_has_every_key_and_matches_the_parquet() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 3, 1, 5), station("B", -1, 0, -1)]);
+        run(&dir, &[]);
+        let parquet = read_parquet(&dir.output());
+        let columns: Vec<String> = parquet
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+
+        for format in ["json", "ndjson"] {
+            run(&dir, &["--format", format, "--overwrite"]);
+            let text = fs::read_to_string(dir.output()).unwrap();
+            let rows: Vec<serde_json::Value> = if format == "json" {
+                serde_json::from_str(&text).unwrap()
+            } else {
+                text.lines()
+                    .map(|line| serde_json::from_str(line).unwrap())
+                    .collect()
+            };
+            assert_eq!(rows.len(), parquet.num_rows(), "{format}");
+            for (index, row) in rows.iter().enumerate() {
+                let mut keys: Vec<&String> = row.as_object().unwrap().keys().collect();
+                keys.sort_unstable();
+                let mut expected: Vec<&String> = columns.iter().collect();
+                expected.sort_unstable();
+                assert_eq!(keys, expected, "{format}");
+                for column in [
+                    "station_ids",
+                    "num_classic_bikes_available",
+                    "num_docks_available",
+                ] {
+                    let value = u16_column(&parquet, column)[index];
+                    assert_eq!(row[column], json!(value), "{format} {column}");
+                }
+            }
+            assert!(rows[1]["num_docks_available"].is_null(), "{format}");
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_sample() {
+        let dir = TempDir::new();
+        let ids: Vec<String> = (0..50).map(|n| n.to_string()).collect();
+        dir.snapshot(T0, ids.iter().map(|id| station(id, 1, 0, 1)).collect());
+        let sample = dir.join("sample.jsonl");
+        let sample_arg = sample.display().to_string();
+        let sample_with = |seed: &str| {
+            run(
+                &dir,
+                &[
+                    "--sample-out",
+                    &sample_arg,
+                    "--sample-n",
+                    "5",
+                    "--seed",
+                    seed,
+                    "--overwrite",
+                ],
+            );
+            fs::read(&sample).unwrap()
+        };
+        let first = sample_with("7");
+        assert_eq!(first.iter().filter(|&&byte| byte == b'\n').count(), 5);
+        assert_eq!(sample_with("7"), first);
+        assert_ne!(sample_with("8"), first);
+    }
+
+    #[test]
+    fn dictionary_station_ids_read_back_as_the_providers_ids() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1), station("B", 1, 0, 1)]);
+        dir.snapshot(T0 + 60, vec![station("B", 2, 0, 0)]);
+        run(
+            &dir,
+            &["--station-id-type", "dictionary", "--row-group-size", "2"],
+        );
+        let batch = read_parquet(&dir.output());
+        assert_eq!(
+            batch
+                .schema()
+                .field_with_name("station_ids")
+                .unwrap()
+                .data_type(),
+            &DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8))
+        );
+        assert_eq!(string_column(&batch, "station_ids"), ["A", "B", "B"]);
+        assert!(!dir.id_map().exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn write_failure_leaves_the_partial_and_says_how_far_it_got() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1), station("B", 1, 0, 1)]);
+        dir.snapshot(T0 + 60, vec![station("A", 2, 0, 0)]);
+        // Every write to /dev/full fails with ENOSPC.
+        let partial = partial_path(&dir.output());
+        std::os::unix::fs::symlink("/dev/full", &partial).unwrap();
+        let args = Args::parse_from(dir.collect_argv(&["--row-group-size", "1"]));
+        let err = collect(&args).unwrap_err().to_string();
+        assert!(err.contains("rows, leaving what was written in"), "{err}");
+        assert!(err.contains(&partial.display().to_string()), "{err}");
+        assert!(fs::symlink_metadata(&partial).is_ok());
+        assert!(!dir.output().exists());
+    }
+
+    #[test]
+    fn min_stations_skips_a_near_empty_snapshot() {
+        let dir = TempDir::new();
+        let ids: Vec<String> = (0..10).map(|n| n.to_string()).collect();
+        let all = || ids.iter().map(|id| station(id, 1, 0, 1)).collect();
+        dir.snapshot(T0, all());
+        dir.snapshot(T0 + 60, vec![station("0", 1, 0, 1)]);
+        dir.snapshot(T0 + 120, all());
+
+        let summary = run(&dir, &["--min-stations", "5"]);
+        assert_eq!((summary.degenerate_snapshots, summary.rows), (1, 20));
+
+        let summary = run(&dir, &["--min-stations-fraction", "0.5", "--overwrite"]);
+        assert_eq!((summary.degenerate_snapshots, summary.rows), (1, 20));
+    }
+
+    #[test]
+    fn flush_writes_a_row_group_and_the_id_map_before_finish() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1), station("B", 1, 0, 1)]);
+        dir.snapshot(T0 + 60, vec![station("C", 1, 0, 1)]);
+        let args = Args::parse_from(dir.collect_argv(&[]));
+        let mut collector = Collector::builder(&args).build().unwrap();
+        let mut maps = Vec::new();
+        for_each_snapshot(&args, |name, snapshot| {
+            collector.process(name, snapshot)?;
+            collector.flush()?;
+            // Nothing buffered, so no empty row group.
+            collector.flush()?;
+            let mut ids: Vec<String> = id_map(&dir).into_keys().collect();
+            ids.sort_unstable();
+            maps.push(ids);
+            assert!(!dir.output().exists());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(maps, [vec!["A", "B"], vec!["A", "B", "C"]]);
+
+        let summary = collector.finish().unwrap();
+        assert_eq!((summary.rows, summary.row_groups), (3, 2));
+        assert_eq!(metadata(&dir.output()).num_row_groups(), 2);
+        assert_eq!(read_parquet(&dir.output()).num_rows(), 3);
+    }
+
+    #[test]
+    fn enforce_monotonic_time_drops_or_clamps_a_backwards_snapshot() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        dir.snapshot(T0 + 120, vec![station("A", 2, 0, 1)]);
+        // Sorts after both, though its feed time is between them.
+        write_gz(
+            &dir.join("station_status/z.json.gz"),
+            &document(T0 + 60, vec![station("A", 3, 0, 1)]),
+        );
+        let seconds = || -> Vec<i64> {
+            times(&read_parquet(&dir.output()))
+                .into_iter()
+                .map(|ms| ms / 1000 - T0)
+                .collect()
+        };
+
+        let summary = run(&dir, &["--enforce-monotonic-time", "drop"]);
+        assert_eq!((summary.backwards_snapshots, summary.rows), (1, 2));
+        assert_eq!(seconds(), [0, 120]);
+
+        let summary = run(&dir, &["--enforce-monotonic-time", "clamp", "--overwrite"]);
+        assert_eq!((summary.backwards_snapshots, summary.rows), (1, 3));
+        assert_eq!(seconds(), [0, 120, 180]);
+    }
+
+    #[test]
+    fn columns_metadata_describes_this_runs_columns() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let path = dir.join("columns.json");
+        let describe = |extra: &[&str]| {
+            let mut argv = vec!["--columns-metadata", path.to_str().unwrap(), "--overwrite"];
+            argv.extend(extra);
+            run(&dir, &argv);
+            let columns = read_json(&path);
+            let description = |name: &str| {
+                let column = columns
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .find(|column| column["name"] == name)
+                    .unwrap_or_else(|| panic!("no {name} column"));
+                column["description"].as_str().unwrap().to_string()
+            };
+            (description("times"), description("num_classic_disabled"))
+        };
+
+        let (times, classic_disabled) = describe(&[]);
+        assert!(times.contains("rounded down"), "{times}");
+        assert!(
+            classic_disabled.contains("combined num_bikes_disabled"),
+            "{classic_disabled}"
+        );
+        let (times, _) = describe(&["--time-rounding", "round"]);
+        assert!(times.contains("nearest"), "{times}");
+        let (times, classic_disabled) = describe(&["--raw"]);
+        assert!(times.contains("to the second"), "{times}");
+        assert!(
+            classic_disabled.contains("null under --raw"),
+            "{classic_disabled}"
+        );
+    }
+
+    #[test]
+    fn builders_are_sized_from_the_first_snapshot() {
+        let dir = TempDir::new();
+        for offset in [0, 60, 120] {
+            dir.snapshot(
+                T0 + offset,
+                vec![station("A", 1, 0, 1), station("B", 1, 0, 1)],
+            );
+        }
+        // The builders' allocation once the first snapshot is in.
+        let allocated = |extra: &[&str]| {
+            let args = Args::parse_from(dir.collect_argv(extra));
+            let mut collector = Collector::builder(&args).build().unwrap();
+            let mut allocated = None;
+            for_each_snapshot(&args, |name, snapshot| {
+                collector.process(name, snapshot)?;
+                allocated.get_or_insert(collector.builders.allocated_bytes());
+                Ok(())
+            })
+            .unwrap();
+            allocated.unwrap()
+        };
+        let columns = Args::parse_from(dir.collect_argv(&[])).columns();
+        let expected = |rows| Builders::with_capacity(&columns, rows).allocated_bytes();
+
+        // Three files of two stations.
+        assert_eq!(allocated(&[]), expected(6));
+        assert_eq!(allocated(&["--row-group-size", "4"]), expected(4));
+        assert_eq!(allocated(&["--builder-capacity", "5"]), expected(5));
+        // Left to grow, so just the two rows appended.
+        assert_eq!(allocated(&["--builder-capacity", "0"]), expected(2));
+    }
+
+    #[test]
+    fn usable_bikes_clamp_at_zero() {
+        let dir = TempDir::new();
+        let mut over = station("A", 3, 0, 1);
+        over["num_bikes_disabled"] = json!(5);
+        let mut under = station("B", 5, 0, 1);
+        under["num_bikes_disabled"] = json!(2);
+        dir.snapshot(T0, vec![over, under]);
+        run(&dir, &["--with-usable-bikes"]);
+        assert_eq!(
+            u16_column(&read_parquet(&dir.output()), "num_bikes_usable"),
+            [Some(0), Some(3)]
+        );
+    }
+
+    #[test]
+    fn warnings_out_lists_each_adjustment_with_its_kind() {
+        let dir = TempDir::new();
+        let first = dir.snapshot(T0, vec![station("A", -1, 0, 1)]);
+        let again = dir.join("station_status/again.json.gz");
+        write_gz(&again, &document(T0, vec![station("A", 1, 0, 1)]));
+        let path = dir.join("warnings.json");
+        let summary = run(&dir, &["--warnings-out", &path.display().to_string()]);
+        assert_eq!(summary.warnings, 2);
+
+        let warnings = read_json(&path);
+        let warnings = warnings.as_array().unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0]["kind"], "count_out_of_range");
+        assert_eq!(warnings[0]["file"], first.display().to_string());
+        assert_eq!(warnings[0]["station_id"], "A");
+        assert_eq!(warnings[0]["time"], T0 * 1000);
+        assert_eq!(warnings[1]["kind"], "duplicate_minute");
+        assert_eq!(warnings[1]["file"], again.display().to_string());
+    }
+
+    #[test]
+    fn row_group_by_keeps_each_row_group_within_a_window() {
+        use parquet::file::statistics::Statistics;
+
+        let dir = TempDir::new();
+        // T0 + 360 is on the hour.
+        for offset in [0, 300, 360, 420, 480] {
+            dir.snapshot(
+                T0 + offset,
+                vec![station("A", 1, 0, 1), station("B", 1, 0, 1)],
+            );
+        }
+        let summary = run(&dir, &["--row-group-by", "1h"]);
+        assert_eq!(summary.row_groups, 2);
+
+        let metadata = metadata(&dir.output());
+        let times = metadata
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .position(|column| column.name() == "times")
+            .unwrap();
+        let hour = |ms: i64| ms.div_euclid(3_600_000);
+        let mut rows = Vec::new();
+        for row_group in metadata.row_groups() {
+            rows.push(row_group.num_rows());
+            let Some(Statistics::Int64(stats)) = row_group.column(times).statistics() else {
+                panic!("no times statistics");
+            };
+            assert_eq!(hour(*stats.min()), hour(*stats.max()));
+        }
+        assert_eq!(rows, [4, 6]);
+    }
+
+    #[test]
+    fn inactive_stations_are_counted_by_status() {
+        let dir = TempDir::new();
+        let with_status = |station_id, status: &str| {
+            let mut station = station(station_id, 1, 0, 1);
+            station["station_status"] = status.into();
+            station
+        };
+        for time in [T0, T0 + 60] {
+            dir.snapshot(
+                time,
+                vec![
+                    station("A", 1, 0, 1),
+                    with_status("B", "planned"),
+                    with_status("C", "decommissioned"),
+                    with_status("D", "decommissioned"),
+                ],
+            );
+        }
+        let summary = run(&dir, &[]);
+        assert_eq!(
+            summary.inactive_stations,
+            BTreeMap::from([
+                ("decommissioned".to_string(), 4),
+                ("planned".to_string(), 2)
+            ])
+        );
+        assert_eq!(read_parquet(&dir.output()).num_rows(), 2);
+    }
+
+    #[test]
+    fn profile_times_every_phase() {
+        let dir = TempDir::new();
+        let stations: Vec<_> = (0..500)
+            .map(|id| station(&format!("S{id}"), 3, 1, 5))
+            .collect();
+        for minute in 0..20 {
+            dir.snapshot(T0 + 60 * minute, stations.clone());
+        }
+        assert!(run(&dir, &[]).profile.is_none());
+        let profile = run(&dir, &["--profile", "--overwrite"]).profile.unwrap();
+        for (phase, ms) in [
+            ("decompress", profile.decompress_ms),
+            ("parse", profile.parse_ms),
+            ("id_lookup", profile.id_lookup_ms),
+            ("append", profile.append_ms),
+            ("flush", profile.flush_ms),
+        ] {
+            assert!(ms > 0.0, "{phase} took no time: {profile:?}");
+        }
+        // Most of the run is spent in the timed phases.
+        assert!(profile.other_ms < profile.total_ms / 2.0, "{profile:?}");
+    }
+
+    #[test]
+    fn raw_output_holds_the_feeds_values() {
+        let dir = TempDir::new();
+        // Off a minute boundary, with counts over the sentinel limits.
+        let time = T0 + 17;
+        let stations = vec![
+            station("A", 7, 2, 3),
+            station("B", 20_000, 0, 12_000),
+            station("C", 0, 0, 0),
+        ];
+        dir.snapshot(time, stations.clone());
+        run(&dir, &["--raw"]);
+
+        let batch = read_parquet(&dir.output());
+        assert_eq!(times(&batch), vec![time * 1000; 3]);
+        let ids = id_map(&dir);
+        let station_ids = u16_column(&batch, "station_ids");
+        for column in [
+            "num_bikes_available",
+            "num_ebikes_available",
+            "num_bikes_disabled",
+            "num_docks_available",
+        ] {
+            let stored = u16_column(&batch, column);
+            for station in &stations {
+                let row = station_ids
+                    .iter()
+                    .position(|id| *id == Some(ids[station["station_id"].as_str().unwrap()]))
+                    .unwrap();
+                assert_eq!(
+                    stored[row].map(i64::from),
+                    station[column].as_i64(),
+                    "{column} of {station}"
+                );
+            }
+        }
+        assert!(batch
+            .column_by_name("num_classic_bikes_available")
+            .is_none());
+    }
+
+    #[test]
+    fn read_buffer_size_leaves_the_output_alone() {
+        let dir = TempDir::new();
+        for time in [T0, T0 + 60] {
+            let stations = (0..50)
+                .map(|n| station(&format!("S{n}"), n, n % 3, 50 - n))
+                .collect();
+            dir.snapshot(time, stations);
+        }
+        let outputs = ["2", "7", "65536"].map(|size| {
+            run(&dir, &["--overwrite", "--read-buffer-size", size]);
+            read_parquet(&dir.output())
+        });
+        assert_eq!(outputs[0].num_rows(), 100);
+        assert!(outputs.iter().all(|batch| *batch == outputs[0]));
+    }
+
+    #[test]
+    fn checkpoint_id_map_is_written_with_each_row_group() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1), station("B", 1, 0, 1)]);
+        dir.snapshot(T0 + 60, vec![station("C", 1, 0, 1), station("D", 1, 0, 1)]);
+        for (checkpoint, expected) in [
+            (false, [None, None]),
+            (true, [Some("A,B"), Some("A,B,C,D")]),
+        ] {
+            let mut extra = vec!["--overwrite", "--row-group-size", "2"];
+            if checkpoint {
+                extra.push("--checkpoint-id-map");
+            }
+            let _ = fs::remove_file(dir.id_map());
+            let args = Args::parse_from(dir.collect_argv(&extra));
+            let mut collector = Collector::builder(&args).build().unwrap();
+            // The stations in the id map on disk after each snapshot.
+            let mut maps = Vec::new();
+            for_each_snapshot(&args, |name, snapshot| {
+                collector.process(name, snapshot)?;
+                maps.push(dir.id_map().exists().then(|| {
+                    let mut ids: Vec<String> = id_map(&dir).into_keys().collect();
+                    ids.sort_unstable();
+                    ids.join(",")
+                }));
+                Ok(())
+            })
+            .unwrap();
+            let maps: Vec<Option<&str>> = maps.iter().map(Option::as_deref).collect();
+            assert_eq!(maps, expected, "checkpoint {checkpoint}");
+            assert_eq!(collector.finish().unwrap().row_groups, 2);
+        }
+    }
+
+    #[test]
+    fn dedup_window_collapses_unchanged_rows_inside_it() {
+        let dir = TempDir::new();
+        for (minute, bikes) in [(0, 1), (1, 1), (2, 1), (3, 2), (4, 1)] {
+            let time = T0 + 60 * minute;
+            let mut jittery = station("A", bikes, 0, 1);
+            jittery["last_reported"] = (time - minute).into();
+            dir.snapshot(time, vec![jittery, station("B", minute, 0, 1)]);
+        }
+        let summary = run(&dir, &["--dedup-window", "2m"]);
+        assert_eq!(summary.near_duplicate_rows, 1);
+
+        let batch = read_parquet(&dir.output());
+        let a = id_map(&dir)["A"];
+        let minutes: Vec<i64> = u16_column(&batch, "station_ids")
+            .into_iter()
+            .zip(times(&batch))
+            .filter(|(station, _)| *station == Some(a))
+            .map(|(_, ms)| (ms / 1000 - T0) / 60)
+            .collect();
+        // Minute 2 is a full window after the kept row at minute 0.
+        assert_eq!(minutes, [0, 2, 3, 4]);
+        assert_eq!(batch.num_rows(), 9);
+    }
+
+    #[test]
+    fn index_lists_the_row_groups_each_station_is_in() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1), station("B", 1, 0, 1)]);
+        dir.snapshot(T0 + 60, vec![station("B", 1, 0, 1), station("C", 1, 0, 1)]);
+        dir.snapshot(T0 + 120, vec![station("A", 1, 0, 1)]);
+        let index_path = dir.join("index.json");
+        let index_arg = index_path.display().to_string();
+        run(&dir, &["--row-group-size", "2", "--emit-index", &index_arg]);
+
+        let index = read_json(&index_path);
+        let ids = id_map(&dir);
+        let row_groups = |name: &str| index["stations"][ids[name].to_string()].clone();
+        assert_eq!(row_groups("A"), json!([0, 2]));
+        assert_eq!(row_groups("B"), json!([0, 1]));
+        assert_eq!(row_groups("C"), json!([1]));
+
+        let footer = metadata(&dir.output());
+        assert_eq!(footer.num_row_groups(), 3);
+        for (n, range) in index["row_groups"].as_array().unwrap().iter().enumerate() {
+            let row_group = footer.row_group(n);
+            assert_eq!(range["rows"], row_group.num_rows());
+            let (start, length) = row_group.column(0).byte_range();
+            assert_eq!(range["offset"], start);
+            assert!(range["length"].as_u64().unwrap() >= length);
+
+            let rows = ParquetRecordBatchReaderBuilder::try_new(File::open(dir.output()).unwrap())
+                .unwrap()
+                .with_row_groups(vec![n])
+                .build()
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap();
+            let mut listed: Vec<u16> = ids
+                .values()
+                .copied()
+                .filter(|id| {
+                    index["stations"][id.to_string()]
+                        .as_array()
+                        .unwrap()
+                        .contains(&json!(n))
+                })
+                .collect();
+            listed.sort_unstable();
+            let mut stored: Vec<u16> = u16_column(&rows, "station_ids")
+                .into_iter()
+                .map(Option::unwrap)
+                .collect();
+            stored.sort_unstable();
+            stored.dedup();
+            assert_eq!(listed, stored, "row group {n}");
+        }
+    }
+
+    #[test]
+    fn renamed_columns_are_in_the_output() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 3, 1, 5)]);
+        run(&dir, &["--rename", "times=ts,num_docks_available=docks"]);
+        let batch = read_parquet(&dir.output());
+        let schema = batch.schema();
+        let names: Vec<&str> = schema
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect();
+        assert!(
+            names.contains(&"ts") && names.contains(&"docks"),
+            "{names:?}"
+        );
+        assert!(!names.contains(&"times") && !names.contains(&"num_docks_available"));
+        assert_eq!(u16_column(&batch, "docks"), [Some(5)]);
+    }
+
+    #[test]
+    fn vehicle_type_columns_fill_absent_types_with_zero() {
+        let dir = TempDir::new();
+        let with_types = |station_id, types: serde_json::Value| {
+            let mut station = station(station_id, 3, 1, 5);
+            station["vehicle_types_available"] = types;
+            station
+        };
+        dir.snapshot(
+            T0,
+            vec![
+                with_types(
+                    "A",
+                    json!([
+                        { "vehicle_type_id": "classic", "count": 2 },
+                        { "vehicle_type_id": "ebike", "count": 1 },
+                    ]),
+                ),
+                with_types("B", json!([{ "vehicle_type_id": "ebike", "count": 4 }])),
+                station("C", 3, 1, 5),
+            ],
+        );
+        let columns_path = dir.join("columns.json");
+        run(
+            &dir,
+            &[
+                "--split-vehicle-availability-by-type",
+                "--columns-metadata",
+                columns_path.to_str().unwrap(),
+            ],
+        );
+
+        let batch = read_parquet(&dir.output());
+        let ids = id_map(&dir);
+        let station_ids = u16_column(&batch, "station_ids");
+        let avail = |column: &str, station_id: &str| {
+            let row = station_ids
+                .iter()
+                .position(|id| *id == Some(ids[station_id]))
+                .unwrap();
+            u16_column(&batch, column)[row]
+        };
+        let counts: Vec<_> = ["A", "B", "C"]
+            .iter()
+            .map(|station_id| {
+                (
+                    avail("avail_classic", station_id),
+                    avail("avail_ebike", station_id),
+                )
+            })
+            .collect();
+        assert_eq!(
+            counts,
+            [(Some(2), Some(1)), (Some(0), Some(4)), (Some(0), Some(0))]
+        );
+
+        let columns = read_json(&columns_path);
+        let described: Vec<_> = columns
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|column| column["name"].as_str().unwrap().starts_with("avail_"))
+            .collect();
+        assert_eq!(described.len(), 2);
+        for column in described {
+            assert_eq!(column["unit"], "vehicles");
+            let description = column["description"].as_str().unwrap();
+            assert!(
+                description.contains("0 where the station doesn't list"),
+                "{description}"
+            );
+        }
+    }
+
+    #[test]
+    fn parquet_version_is_the_footers_version() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 3, 1, 5)]);
+        for (flag, version) in [(None, 1), (Some("1.0"), 1), (Some("2.0"), 2)] {
+            let mut extra = vec!["--overwrite"];
+            extra.extend(
+                flag.map(|flag| ["--parquet-version", flag])
+                    .into_iter()
+                    .flatten(),
+            );
+            run(&dir, &extra);
+            let footer = metadata(&dir.output());
+            assert_eq!(footer.file_metadata().version(), version, "{flag:?}");
+            assert_eq!(read_parquet(&dir.output()).num_rows(), 1);
+        }
+    }
+
+    #[test]
+    fn with_8d_stores_the_flag_or_null() {
+        let dir = TempDir::new();
+        let with_keys = |station_id, keys: bool| {
+            let mut station = station(station_id, 1, 0, 1);
+            station["eightd_has_available_keys"] = keys.into();
+            station
+        };
+        dir.snapshot(
+            T0,
+            vec![
+                with_keys("A", true),
+                with_keys("B", false),
+                station("C", 1, 0, 1),
+            ],
+        );
+        run(&dir, &[]);
+        let batch = read_parquet(&dir.output());
+        assert!(batch.column_by_name("eightd_has_available_keys").is_none());
+
+        run(&dir, &["--overwrite", "--with-8d"]);
+        let batch = read_parquet(&dir.output());
+        let ids = id_map(&dir);
+        let station_ids = u16_column(&batch, "station_ids");
+        let keys = batch
+            .column_by_name("eightd_has_available_keys")
+            .unwrap()
+            .as_boolean();
+        let stored: Vec<Option<bool>> = ["A", "B", "C"]
+            .iter()
+            .map(|name| {
+                let row = station_ids
+                    .iter()
+                    .position(|id| *id == Some(ids[*name]))
+                    .unwrap();
+                keys.is_valid(row).then(|| keys.value(row))
+            })
+            .collect();
+        assert_eq!(stored, [Some(true), Some(false), None]);
+    }
+
+    #[test]
+    fn station_counts_histogram_buckets_snapshots_by_active_stations() {
+        let dir = TempDir::new();
+        let stations = |count: usize| -> Vec<_> {
+            (0..count)
+                .map(|n| station(&format!("S{n}"), 1, 0, 1))
+                .collect()
+        };
+        let mut partial = stations(2);
+        let mut planned = station("P", 1, 0, 1);
+        planned["station_status"] = "planned".into();
+        partial.push(planned);
+        for (minute, snapshot) in [stations(3), stations(3), partial, stations(5)]
+            .into_iter()
+            .enumerate()
+        {
+            dir.snapshot(T0 + 60 * minute as i64, snapshot);
+        }
+        let path = dir.join("station_counts.json");
+        run(&dir, &["--report-station-counts", path.to_str().unwrap()]);
+        assert_eq!(
+            read_json(&path),
+            json!({
+                "snapshots": 4,
+                "min": 2,
+                "median": 3,
+                "max": 5,
+                "histogram": { "2": 1, "3": 2, "5": 1 },
+            })
+        );
+    }
+
+    #[test]
+    fn arrow_memory_pool_limit_fails_once_the_builders_outgrow_it() {
+        let dir = TempDir::new();
+        for time in [T0, T0 + 60] {
+            let stations = (0..10)
+                .map(|n| station(&format!("S{n}"), 1, 0, 1))
+                .collect();
+            dir.snapshot(time, stations);
+        }
+        let args = Args::parse_from(dir.collect_argv(&["--arrow-memory-pool-limit", "100"]));
+        let err = collect(&args).unwrap_err().to_string();
+        assert!(err.starts_with("memory limit exceeded"), "{err}");
+        assert!(err.ends_with("over --arrow-memory-pool-limit 100"), "{err}");
+        assert!(!dir.output().exists());
+
+        let summary = run(&dir, &["--arrow-memory-pool-limit", "100000000"]);
+        assert_eq!(summary.rows, 20);
+    }
+
+    #[test]
+    fn window_days_drops_rows_older_than_the_window_on_the_next_run() {
+        const DAY: i64 = 24 * 60 * 60;
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        dir.snapshot(T0 + DAY, vec![station("A", 2, 0, 1)]);
+        let extra = ["--window-days", "2", "--reuse-id-map", "--overwrite"];
+        let days = || -> Vec<i64> {
+            times(&read_parquet(&dir.output()))
+                .into_iter()
+                .map(|ms| (ms / 1000 - T0) / DAY)
+                .collect()
+        };
+        run(&dir, &extra);
+        assert_eq!(days(), [0, 1]);
+
+        dir.snapshot(T0 + 3 * DAY, vec![station("A", 3, 0, 1)]);
+        let summary = run(&dir, &extra);
+        assert_eq!(days(), [1, 3]);
+        assert_eq!(
+            (summary.window_kept_rows, summary.window_dropped_rows),
+            (1, 1)
+        );
+        assert_eq!(
+            u16_column(&read_parquet(&dir.output()), "num_classic_bikes_available"),
+            [Some(2), Some(3)]
+        );
+    }
+
+    #[test]
+    fn input_list_collects_exactly_the_listed_files() {
+        let dir = TempDir::new();
+        let paths: Vec<PathBuf> = (0..4)
+            .map(|minute| dir.snapshot(T0 + 60 * minute, vec![station("A", minute, 0, 1)]))
+            .collect();
+        let list = dir.join("files.txt");
+        fs::write(
+            &list,
+            format!(
+                "# today's snapshots\n\n{}\n  {}  \n{}\n",
+                paths[3].display(),
+                paths[1].display(),
+                dir.join("station_status/missing.json.gz").display()
+            ),
+        )
+        .unwrap();
+        let argv = [
+            "bike-1".to_string(),
+            "--input-list".to_string(),
+            list.display().to_string(),
+            "--output".to_string(),
+            dir.output().display().to_string(),
+            "--id-map".to_string(),
+            dir.id_map().display().to_string(),
+        ];
+        let summary = collect(&Args::parse_from(&argv)).unwrap();
+        assert_eq!((summary.files, summary.skipped_files), (2, 1));
+        let minutes: Vec<i64> = times(&read_parquet(&dir.output()))
+            .into_iter()
+            .map(|ms| (ms / 1000 - T0) / 60)
+            .collect();
+        assert_eq!(minutes, [3, 1]);
+
+        let mut argv = argv.to_vec();
+        argv.extend(["--on-error", "fail", "--overwrite"].map(String::from));
+        let err = collect(&Args::parse_from(&argv)).unwrap_err().to_string();
+        assert!(err.contains("missing.json.gz"), "{err}");
+    }
+
+    #[test]
+    fn durability_sets_how_often_the_output_is_synced() {
+        let dir = TempDir::new();
+        for minute in 0..3 {
+            dir.snapshot(
+                T0 + 60 * minute,
+                vec![station("A", 1, 0, 1), station("B", 1, 0, 1)],
+            );
+        }
+        let syncs = |durability| {
+            let summary = run(
+                &dir,
+                &[
+                    "--overwrite",
+                    "--row-group-size",
+                    "2",
+                    "--durability",
+                    durability,
+                ],
+            );
+            assert_eq!(summary.row_groups, 3);
+            summary.syncs
+        };
+        assert_eq!(syncs("none"), 0);
+        // Once per row group, then once more for the footer.
+        assert_eq!(syncs("batch"), 4);
+        assert_eq!(syncs("close"), 1);
+    }
+
+    #[test]
+    fn collectors_sharing_a_locked_id_map_never_share_an_id() {
+        let dirs = [TempDir::new(), TempDir::new()];
+        let shared = dirs[0].id_map();
+        for (dir, prefix) in dirs.iter().zip(["S", "T"]) {
+            for minute in 0..20 {
+                let stations = vec![
+                    station(&format!("{prefix}{minute}"), 1, 0, 1),
+                    station("both", 1, 0, 1),
+                ];
+                dir.snapshot(T0 + 60 * minute, stations);
+            }
+        }
+        std::thread::scope(|scope| {
+            for dir in &dirs {
+                let shared = &shared;
+                scope.spawn(move || {
+                    let mut args =
+                        Args::parse_from(dir.collect_argv(&[
+                            "--reuse-id-map",
+                            "--coalesce-id-map-writes-with-lock",
+                        ]));
+                    args.id_map = shared.clone();
+                    collect(&args).unwrap();
+                });
+            }
+        });
+
+        let legend = read_id_map(&shared).unwrap();
+        assert_eq!(legend.len(), 41);
+        let names: HashMap<u16, &str> = legend
+            .iter()
+            .map(|(name, id)| (*id, name.as_str()))
+            .collect();
+        assert_eq!(names.len(), 41, "two stations share an id: {legend:?}");
+        for (dir, prefix) in dirs.iter().zip(["S", "T"]) {
+            let batch = read_parquet(&dir.output());
+            for id in u16_column(&batch, "station_ids") {
+                let name = names[&id.unwrap()];
+                assert!(name.starts_with(prefix) || name == "both", "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn other_feeds_dir_writes_each_feed_out() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let write = |name: &str, last_updated: i64, data: serde_json::Value| {
+            let document = json!({"last_updated": last_updated, "ttl": 60, "data": data});
+            write_gz(&dir.join(format!("station_status/{name}")), &document);
+        };
+        write(
+            "station_information.json.gz",
+            T0,
+            json!({"stations": [
+                {"station_id": "A", "name": "Main St", "lat": 40.7, "lon": -74.0, "capacity": 19},
+                {"station_id": "B", "name": "Elm St", "lat": 40.8, "lon": -73.9},
+            ]}),
+        );
+        // Told apart by its content, and in GBFS 1.x's 0/1 flags.
+        write(
+            "1688000100.json.gz",
+            T0 + 60,
+            json!({"bikes": [
+                {"bike_id": "b1", "lat": 40.7, "lon": -74.0, "is_reserved": 0, "is_disabled": 1},
+            ]}),
+        );
+        write(
+            "free_bike_status_1688000160.json.gz",
+            T0 + 120,
+            json!({"bikes": [
+                {"bike_id": "b2", "lat": 40.7, "lon": -74.0, "is_reserved": true,
+                 "is_disabled": false, "vehicle_type_id": "2"},
+            ]}),
+        );
+        // Not one that's written out, so still skipped.
+        write("vehicle_types.json.gz", T0, json!({"vehicle_types": []}));
+
+        let feeds = dir.join("feeds");
+        let summary = run(
+            &dir,
+            &[
+                "--on-error",
+                "fail",
+                "--other-feeds-dir",
+                feeds.to_str().unwrap(),
+            ],
+        );
+        assert_eq!(summary.rows, 1);
+        assert_eq!(summary.other_feed_files, 3);
+        assert_eq!(
+            summary.other_feed_rows,
+            BTreeMap::from([
+                ("free_bike_status".to_string(), 2),
+                ("station_information".to_string(), 2)
+            ])
+        );
+
+        let information = read_parquet(&feeds.join("station_information.parquet"));
+        assert_eq!(times(&information), [T0 * 1000; 2]);
+        assert_eq!(string_column(&information, "name"), ["Main St", "Elm St"]);
+        let capacity = information.column_by_name("capacity").unwrap();
+        assert_eq!(
+            capacity
+                .as_primitive::<Int64Type>()
+                .iter()
+                .collect::<Vec<_>>(),
+            [Some(19), None]
+        );
+
+        let bikes = read_parquet(&feeds.join("free_bike_status.parquet"));
+        assert_eq!(times(&bikes), [(T0 + 60) * 1000, (T0 + 120) * 1000]);
+        assert_eq!(string_column(&bikes, "bike_id"), ["b1", "b2"]);
+        let flag = |name| {
+            let column = bikes.column_by_name(name).unwrap().as_boolean();
+            column.iter().flatten().collect::<Vec<_>>()
+        };
+        assert_eq!(flag("is_reserved"), [false, true]);
+        assert_eq!(flag("is_disabled"), [true, false]);
+        assert!(!feeds.join("vehicle_types.parquet").exists());
+    }
+
+    #[test]
+    fn a_snapshot_only_sniffed_as_another_feed_is_skipped_with_a_warning() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let document = json!({"last_updated": T0 + 60, "ttl": 60, "data": {
+            "stations": [{"station_id": "A", "name": "Main St", "lat": 40.7, "lon": -74.0}],
+        }});
+        write_gz(&dir.join("station_status/1688000100.json.gz"), &document);
+
+        let warnings = dir.join("warnings.json");
+        let summary = run(&dir, &["--warnings-out", warnings.to_str().unwrap()]);
+        assert_eq!(summary.rows, 1);
+        assert_eq!(summary.skipped_files, 1);
+        let warnings = read_json(&warnings);
+        assert_eq!(warnings[0]["kind"], "unreadable_snapshot");
+        assert_eq!(
+            warnings[0]["detail"],
+            "looks like a station_information snapshot, not station_status"
+        );
+    }
+}
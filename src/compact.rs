@@ -0,0 +1,180 @@
+use glob::glob;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::args::Codec;
+use crate::collector::{partial_path, refuse_overwrite};
+use crate::compression::{pick_codec, properties};
+use crate::{Args, Result};
+
+/// Rewrites every parquet file matching `pattern` into `output`, in
+/// row groups of `row_group_size` rows. The inputs must share a schema,
+/// which is checked from their footers before anything is written. The
+/// output is written as `<output>.partial` and renamed once its row count
+/// has been checked against the inputs'; with `delete_inputs` they are
+/// then removed. Returns the rows written.
+pub fn compact(
+    args: &Args,
+    pattern: &str,
+    output: &Path,
+    row_group_size: usize,
+    delete_inputs: bool,
+) -> Result<i64> {
+    refuse_overwrite(args, output)?;
+    let inputs: Vec<PathBuf> = glob(pattern)?
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|input| input != output)
+        .collect();
+
+    let mut schema = None;
+    let mut schema_version = None;
+    let mut input_rows = 0;
+    for input in &inputs {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(input)?)?;
+        input_rows += builder.metadata().file_metadata().num_rows();
+        match &schema {
+            None => {
+                schema = Some(builder.schema().clone());
+                schema_version = builder
+                    .metadata()
+                    .file_metadata()
+                    .key_value_metadata()
+                    .and_then(|kvs| kvs.iter().find(|kv| kv.key == "schema_version"))
+                    .and_then(|kv| kv.value.clone());
+            }
+            Some(schema) if schema.fields() != builder.schema().fields() => {
+                return Err(format!("{} has a different schema", input.display()).into());
+            }
+            Some(_) => {}
+        }
+    }
+
+    let partial = partial_path(output);
+    let mut writer: Option<ArrowWriter<File>> = None;
+    for input in &inputs {
+        info!(file = %input.display(), "compacting");
+        for batch in ParquetRecordBatchReaderBuilder::try_new(File::open(input)?)?.build()? {
+            let batch = batch?;
+            let writer = match &mut writer {
+                Some(writer) => writer,
+                None => {
+                    let schema = batch.schema();
+                    let codec = match args.compression {
+                        Codec::Auto => pick_codec(
+                            &schema,
+                            &batch,
+                            args.parquet_version,
+                            row_group_size,
+                            args.compression_time_weight,
+                        )?,
+                        codec => codec,
+                    };
+                    let props = properties(
+                        codec,
+                        args.parquet_version,
+                        row_group_size,
+                        schema_version.as_deref(),
+                    );
+                    writer.insert(ArrowWriter::try_new(
+                        File::create(&partial)?,
+                        schema,
+                        Some(props),
+                    )?)
+                }
+            };
+            writer.write(&batch)?;
+        }
+    }
+
+    let Some(mut writer) = writer else {
+        return Err(format!("no rows in any file matching {pattern}").into());
+    };
+    writer.flush()?;
+    let output_rows = writer
+        .flushed_row_groups()
+        .iter()
+        .map(|row_group| row_group.num_rows())
+        .sum();
+    // Unlike close, this flushes the writer's buffer rather than dropping
+    // it, so the footer failing to write is an error.
+    writer.into_inner()?;
+    if output_rows != input_rows {
+        return Err(format!(
+            "{} has {output_rows} rows but its inputs had {input_rows}; inputs left in place",
+            partial.display()
+        )
+        .into());
+    }
+    fs::rename(&partial, output)?;
+    if delete_inputs {
+        for input in &inputs {
+            fs::remove_file(input)?;
+        }
+    }
+    Ok(output_rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::collector::collect;
+    use crate::test_fixtures::{metadata, read_parquet, station, u16_column, TempDir, T0};
+
+    /// Collects one two-station snapshot per entry of `extra` into
+    /// `part-<n>.parquet`, with that entry's flags, returning the glob
+    /// matching them all.
+    fn parts(dir: &TempDir, extra: &[&[&str]]) -> String {
+        for (n, flags) in extra.iter().enumerate() {
+            let snapshot = dir.snapshot(
+                T0 + 60 * n as i64,
+                vec![station("A", n as i64, 0, 1), station("B", 1, 0, 1)],
+            );
+            let flags: Vec<&str> = ["--reuse-id-map"].iter().chain(*flags).copied().collect();
+            collect(&Args::parse_from(dir.collect_argv(&flags))).unwrap();
+            fs::rename(dir.output(), dir.join(format!("part-{n}.parquet"))).unwrap();
+            fs::remove_file(snapshot).unwrap();
+        }
+        dir.join("part-*.parquet").display().to_string()
+    }
+
+    #[test]
+    fn compacts_several_files_into_one_row_group() {
+        let dir = TempDir::new();
+        let pattern = parts(&dir, &[&[], &[], &[]]);
+        let output = dir.join("compacted.parquet");
+        let args = Args::parse_from(["bike-1"]);
+        assert_eq!(compact(&args, &pattern, &output, 1024, true).unwrap(), 6);
+
+        assert_eq!(metadata(&output).num_row_groups(), 1);
+        assert_eq!(
+            u16_column(&read_parquet(&output), "num_classic_bikes_available"),
+            [Some(0), Some(1), Some(1), Some(1), Some(2), Some(1)]
+        );
+        assert!(!partial_path(&output).exists());
+        assert!(glob(&pattern).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn a_different_schema_fails_before_writing() {
+        let dir = TempDir::new();
+        let pattern = parts(&dir, &[&[], &["--with-source"]]);
+        let output = dir.join("compacted.parquet");
+        let args = Args::parse_from(["bike-1"]);
+        let err = compact(&args, &pattern, &output, 1024, true)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("part-1.parquet has a different schema"),
+            "{err}"
+        );
+        assert!(!output.exists());
+        assert!(!partial_path(&output).exists());
+        assert_eq!(glob(&pattern).unwrap().count(), 2);
+    }
+}
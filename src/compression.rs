@@ -0,0 +1,87 @@
+use arrow::datatypes::SchemaRef;
+use arrow_array::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::{WriterProperties, WriterVersion};
+use std::time::Instant;
+use tracing::info;
+
+use crate::args::{Codec, ParquetVersion};
+use crate::Result;
+
+/// Writer properties for an output, recording the codec and schema version
+/// in the footer.
+pub(crate) fn properties(
+    codec: Codec,
+    version: ParquetVersion,
+    row_group_size: usize,
+    schema_version: Option<&str>,
+) -> WriterProperties {
+    let compression = match codec {
+        Codec::Uncompressed | Codec::Auto => Compression::UNCOMPRESSED,
+        Codec::Snappy => Compression::SNAPPY,
+        Codec::Zstd => Compression::ZSTD(ZstdLevel::default()),
+    };
+    let mut kvs = vec![KeyValue::new(
+        "compression".to_string(),
+        codec.name().to_string(),
+    )];
+    if let Some(version) = schema_version {
+        kvs.push(KeyValue::new(
+            "schema_version".to_string(),
+            version.to_string(),
+        ));
+    }
+    let writer_version = match version {
+        ParquetVersion::V1 => WriterVersion::PARQUET_1_0,
+        ParquetVersion::V2 => WriterVersion::PARQUET_2_0,
+    };
+    WriterProperties::builder()
+        .set_writer_version(writer_version)
+        .set_max_row_group_size(row_group_size)
+        .set_compression(compression)
+        .set_key_value_metadata(Some(kvs))
+        .build()
+}
+
+/// Writes `batch` with snappy and with zstd, and picks whichever has the
+/// lower size + `weight` × time, each relative to the better of the two.
+pub(crate) fn pick_codec(
+    schema: &SchemaRef,
+    batch: &RecordBatch,
+    version: ParquetVersion,
+    row_group_size: usize,
+    weight: f64,
+) -> Result<Codec> {
+    let mut trials = Vec::new();
+    for codec in [Codec::Snappy, Codec::Zstd] {
+        let started = Instant::now();
+        let mut buf = Vec::new();
+        let props = properties(codec, version, row_group_size, None);
+        let mut writer = ArrowWriter::try_new(&mut buf, schema.clone(), Some(props))?;
+        writer.write(batch)?;
+        writer.close()?;
+        let seconds = started.elapsed().as_secs_f64();
+        info!(
+            codec = codec.name(),
+            bytes = buf.len(),
+            ms = seconds * 1000.0,
+            "compression trial"
+        );
+        trials.push((codec, buf.len() as f64, seconds));
+    }
+    let min_size = trials.iter().map(|t| t.1).fold(f64::INFINITY, f64::min);
+    let min_time = trials
+        .iter()
+        .map(|t| t.2)
+        .fold(f64::INFINITY, f64::min)
+        .max(1e-9);
+    let score = |(_, size, time): &(Codec, f64, f64)| size / min_size + weight * time / min_time;
+    let (codec, _, _) = trials
+        .iter()
+        .min_by(|a, b| score(a).total_cmp(&score(b)))
+        .unwrap();
+    info!(codec = codec.name(), "picked compression");
+    Ok(*codec)
+}
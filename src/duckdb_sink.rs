@@ -0,0 +1,81 @@
+use crate::row::{Columns, Row};
+use crate::Result;
+use duckdb::{params, Connection};
+use std::path::Path;
+
+/// Incremental DuckDB output. Rows are keyed on (station_ids, times) so
+/// re-running over overlapping input leaves existing rows alone. That only
+/// holds if station ids are stable between runs.
+pub struct DuckDbSink {
+    conn: Connection,
+    columns: Columns,
+}
+
+impl DuckDbSink {
+    /// Opens or creates the table, its bikes column named as in the parquet
+    /// output's `columns`. Refuses a table made with the other
+    /// `--bike-split`, whose values would land under the wrong name.
+    pub fn open(path: &Path, columns: Columns) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS station_status (
+                station_ids USMALLINT NOT NULL,
+                {} USMALLINT,
+                num_ebikes_available USMALLINT,
+                num_bikes_disabled USMALLINT,
+                num_ebikes_disabled USMALLINT,
+                num_classic_disabled USMALLINT,
+                num_docks_available USMALLINT,
+                is_returning BOOLEAN,
+                times TIMESTAMP NOT NULL,
+                source_file VARCHAR,
+                PRIMARY KEY (station_ids, times)
+            );",
+            columns.bikes_available()
+        ))?;
+        let matching: usize = conn.query_row(
+            "SELECT count(*) FROM information_schema.columns
+            WHERE table_name = 'station_status' AND column_name = ?",
+            [columns.bikes_available()],
+            |row| row.get(0),
+        )?;
+        if matching == 0 {
+            return Err(format!(
+                "{}'s station_status table has no {} column; it was made with a different --bike-split",
+                path.display(),
+                columns.bikes_available()
+            )
+            .into());
+        }
+        Ok(DuckDbSink { conn, columns })
+    }
+
+    /// Inserts a batch of rows in one transaction, returning how many were
+    /// new.
+    pub fn insert(&mut self, rows: &[Row]) -> duckdb::Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut inserted = 0;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO station_status VALUES (?, ?, ?, ?, ?, ?, ?, ?, epoch_ms(?), ?)
+                ON CONFLICT DO NOTHING",
+            )?;
+            for row in rows {
+                inserted += stmt.execute(params![
+                    row.station_id,
+                    self.columns.bikes(row),
+                    row.num_ebikes_available,
+                    row.num_bikes_disabled,
+                    row.num_ebikes_disabled,
+                    row.num_classic_disabled,
+                    row.num_docks_available,
+                    row.is_returning,
+                    row.time,
+                    row.source_file,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(inserted)
+    }
+}
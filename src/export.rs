@@ -0,0 +1,288 @@
+use arrow::array::{Array, AsArray};
+use arrow::compute::filter_record_batch;
+use arrow::compute::kernels::comparison::eq_scalar;
+use arrow::datatypes::DataType;
+use arrow_array::types::{Int32Type, TimestampMillisecondType, UInt16Type};
+use arrow_array::{RecordBatch, UInt16Array};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ProjectionMask;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::collector::{read_id_map, refuse_overwrite};
+use crate::{Args, Result};
+
+#[derive(Debug, Serialize)]
+struct Document {
+    last_updated: i64,
+    ttl: u32,
+    data: Data,
+}
+
+#[derive(Debug, Serialize)]
+struct Data {
+    stations: Vec<Station>,
+}
+
+#[derive(Debug, Serialize)]
+struct Station {
+    station_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_bikes_available: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_ebikes_available: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_bikes_disabled: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_docks_available: Option<i64>,
+    num_docks_disabled: i64,
+    is_installed: u32,
+    is_renting: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_returning: Option<u32>,
+    last_reported: i64,
+    station_status: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    vehicle_types_available: Vec<VehicleTypeAvailable>,
+}
+
+#[derive(Debug, Serialize)]
+struct VehicleTypeAvailable {
+    vehicle_type_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_bikes_disabled: Option<i64>,
+}
+
+/// Writes the rows at the time nearest `time` (milliseconds since the epoch)
+/// back out as a station_status document, returning the time used and how
+/// many stations it holds.
+///
+/// Only what the output keeps comes back. Every station is active,
+/// installed and renting, with no disabled docks, and reports at the
+/// snapshot time. Null counts and `is_returning` are left out rather than
+/// made up. A disabled breakdown is split into the first of
+/// `--ebike-vehicle-types` and the lowest numbered type that isn't one.
+/// Integer station ids are mapped back through `--id-map`.
+pub fn export_snapshot(args: &Args, path: &Path, time: i64, out: &Path) -> Result<(i64, usize)> {
+    refuse_overwrite(args, out)?;
+    let nearest =
+        nearest_time(path, time)?.ok_or_else(|| format!("{} has no rows", path.display()))?;
+
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let mapped = builder.schema().field_with_name("station_ids")?.data_type() == &DataType::UInt16;
+    let names: HashMap<u16, String> = if mapped {
+        read_id_map(&args.id_map)?
+            .into_iter()
+            .map(|(name, id)| (id, name))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let ebike_type = args
+        .ebike_vehicle_types
+        .first()
+        .ok_or("--ebike-vehicle-types is empty")?;
+    let classic_type = (1..)
+        .map(|id: u32| id.to_string())
+        .find(|id| !args.ebike_vehicle_types.contains(id))
+        .unwrap();
+    let vehicle_types = (ebike_type.as_str(), classic_type.as_str());
+
+    let mut stations = Vec::new();
+    for batch in builder.build()? {
+        let batch = batch?;
+        let times = batch
+            .column_by_name("times")
+            .ok_or("no times column")?
+            .as_primitive::<TimestampMillisecondType>();
+        let rows = filter_record_batch(&batch, &eq_scalar(times, nearest)?)?;
+        stations.extend(to_stations(&rows, &names, vehicle_types, nearest / 1000)?);
+    }
+
+    let document = Document {
+        last_updated: nearest / 1000,
+        ttl: 0,
+        data: Data { stations },
+    };
+    let mut file = BufWriter::new(File::create(out)?);
+    serde_json::to_writer_pretty(&mut file, &document)?;
+    file.flush()?;
+    Ok((nearest, document.data.stations.len()))
+}
+
+/// The stored time closest to `time`, the earlier one on a tie, reading
+/// only the `times` column.
+fn nearest_time(path: &Path, time: i64) -> Result<Option<i64>> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let column = builder.schema().index_of("times")?;
+    let mask = ProjectionMask::roots(builder.parquet_schema(), [column]);
+    let mut nearest: Option<i64> = None;
+    for batch in builder.with_projection(mask).build()? {
+        let batch = batch?;
+        for stored in batch
+            .column(0)
+            .as_primitive::<TimestampMillisecondType>()
+            .values()
+        {
+            let closer = nearest.is_none_or(|nearest| {
+                let (gap, best) = (stored.abs_diff(time), nearest.abs_diff(time));
+                gap < best || gap == best && *stored < nearest
+            });
+            if closer {
+                nearest = Some(*stored);
+            }
+        }
+    }
+    Ok(nearest)
+}
+
+/// The stations of `batch`, with a disabled breakdown under the
+/// `(ebike, classic)` vehicle type ids.
+fn to_stations(
+    batch: &RecordBatch,
+    names: &HashMap<u16, String>,
+    (ebike_type, classic_type): (&str, &str),
+    time: i64,
+) -> Result<Vec<Station>> {
+    let count = |name: &str| -> Result<&UInt16Array> {
+        Ok(batch
+            .column_by_name(name)
+            .ok_or_else(|| format!("no {name} column"))?
+            .as_primitive::<UInt16Type>())
+    };
+    let value =
+        |array: &UInt16Array, row: usize| array.is_valid(row).then(|| i64::from(array.value(row)));
+    let ebikes = count("num_ebikes_available")?;
+    // Classic-only outputs get their ebikes added back.
+    let (bikes, classic_only) = match batch.column_by_name("num_classic_bikes_available") {
+        Some(classic) => (classic.as_primitive::<UInt16Type>(), true),
+        None => (count("num_bikes_available")?, false),
+    };
+    let disabled = count("num_bikes_disabled")?;
+    let ebikes_disabled = count("num_ebikes_disabled")?;
+    let classic_disabled = count("num_classic_disabled")?;
+    let docks = count("num_docks_available")?;
+    let returning = batch
+        .column_by_name("is_returning")
+        .ok_or("no is_returning column")?
+        .as_boolean();
+    let station_ids = batch
+        .column_by_name("station_ids")
+        .ok_or("no station_ids column")?;
+
+    (0..batch.num_rows())
+        .map(|row| {
+            let station_id = match station_ids.data_type() {
+                DataType::UInt16 => {
+                    let id = station_ids.as_primitive::<UInt16Type>().value(row);
+                    names
+                        .get(&id)
+                        .cloned()
+                        .ok_or_else(|| format!("station id {id} isn't in the id map"))?
+                }
+                _ => {
+                    let dictionary = station_ids.as_dictionary::<Int32Type>();
+                    let key = dictionary.keys().value(row) as usize;
+                    dictionary
+                        .values()
+                        .as_string::<i32>()
+                        .value(key)
+                        .to_string()
+                }
+            };
+            let num_bikes_available = match (classic_only, value(bikes, row), value(ebikes, row)) {
+                (true, Some(classic), Some(ebikes)) => Some(classic + ebikes),
+                (true, _, _) => None,
+                (false, total, _) => total,
+            };
+            let vehicle_types_available = if ebikes_disabled.is_null(row) {
+                Vec::new()
+            } else {
+                vec![
+                    VehicleTypeAvailable {
+                        vehicle_type_id: ebike_type.to_string(),
+                        num_bikes_disabled: value(ebikes_disabled, row),
+                    },
+                    VehicleTypeAvailable {
+                        vehicle_type_id: classic_type.to_string(),
+                        num_bikes_disabled: value(classic_disabled, row),
+                    },
+                ]
+            };
+            Ok(Station {
+                station_id,
+                num_bikes_available,
+                num_ebikes_available: value(ebikes, row),
+                num_bikes_disabled: value(disabled, row),
+                num_docks_available: value(docks, row),
+                num_docks_disabled: 0,
+                is_installed: 1,
+                is_renting: 1,
+                is_returning: returning
+                    .is_valid(row)
+                    .then(|| u32::from(returning.value(row))),
+                last_reported: time,
+                station_status: "active",
+                vehicle_types_available,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::collector::collect;
+    use crate::test_fixtures::{document, read_json, station, TempDir, T0};
+
+    #[test]
+    fn a_snapshot_round_trips_through_the_parquet() {
+        let dir = TempDir::new();
+        let stations = vec![station("A", 3, 1, 5), station("B", 0, 0, 9)];
+        dir.snapshot(T0, stations.clone());
+        dir.snapshot(T0 + 60, vec![station("A", 4, 1, 4), station("B", 1, 0, 8)]);
+        let args = Args::parse_from(dir.collect_argv(&[]));
+        collect(&args).unwrap();
+
+        let out = dir.join("exported.json");
+        let (time, count) = export_snapshot(&args, &dir.output(), (T0 + 20) * 1000, &out).unwrap();
+        assert_eq!((time, count), (T0 * 1000, 2));
+        let mut expected = document(T0, stations);
+        expected["ttl"] = 0.into();
+        assert_eq!(read_json(&out), expected);
+    }
+
+    #[test]
+    fn nulls_are_left_out_and_the_breakdown_uses_the_ebike_types() {
+        let dir = TempDir::new();
+        let mut fixture = station("A", 3, 1, -1);
+        fixture["is_returning"] = 7.into();
+        fixture["num_bikes_disabled"] = 3.into();
+        fixture["vehicle_types_available"] = serde_json::json!([
+            {"vehicle_type_id": "1", "count": 2, "num_bikes_disabled": 1},
+            {"vehicle_type_id": "e", "count": 1, "num_bikes_disabled": 2},
+        ]);
+        dir.snapshot(T0, vec![fixture]);
+        let args = Args::parse_from(dir.collect_argv(&["--ebike-vehicle-types", "e"]));
+        collect(&args).unwrap();
+
+        let out = dir.join("exported.json");
+        export_snapshot(&args, &dir.output(), T0 * 1000, &out).unwrap();
+        let exported = &read_json(&out)["data"]["stations"][0];
+        assert!(exported.get("num_docks_available").is_none(), "{exported}");
+        assert!(exported.get("is_returning").is_none(), "{exported}");
+        assert_eq!(
+            exported["vehicle_types_available"],
+            serde_json::json!([
+                {"vehicle_type_id": "e", "num_bikes_disabled": 2},
+                {"vehicle_type_id": "1", "num_bikes_disabled": 1},
+            ])
+        );
+    }
+}
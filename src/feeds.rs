@@ -0,0 +1,226 @@
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow_array::{
+    ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray,
+    TimestampMillisecondArray,
+};
+use parquet::arrow::ArrowWriter;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::args::Codec;
+use crate::collector::{partial_path, refuse_overwrite};
+use crate::compression::properties;
+use crate::snapshot::{epoch_millis, OtherFeed};
+use crate::{Args, Result};
+
+/// The feeds besides station_status that `--other-feeds-dir` writes out.
+pub(crate) const FEED_OUTPUTS: &[&str] = &["station_information", "free_bike_status"];
+
+#[derive(Deserialize)]
+struct Document<T> {
+    last_updated: i64,
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct StationInformation {
+    stations: Vec<StationInfo>,
+}
+
+#[derive(Deserialize)]
+struct StationInfo {
+    station_id: String,
+    name: String,
+    lat: f64,
+    lon: f64,
+    #[serde(default)]
+    capacity: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct FreeBikeStatus {
+    bikes: Vec<Bike>,
+}
+
+#[derive(Deserialize)]
+struct Bike {
+    bike_id: String,
+    /// Left out for a bike reserved at a station, from GBFS 2.1.
+    #[serde(default)]
+    lat: Option<f64>,
+    #[serde(default)]
+    lon: Option<f64>,
+    is_reserved: Flag,
+    is_disabled: Flag,
+    #[serde(default)]
+    vehicle_type_id: Option<String>,
+}
+
+/// GBFS 1.x sends flags as 0 or 1, and 2.x as booleans.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Flag {
+    Bool(bool),
+    Int(u8),
+}
+
+impl Flag {
+    fn get(&self) -> bool {
+        matches!(self, Flag::Bool(true) | Flag::Int(1))
+    }
+}
+
+/// `<dir>/<feed>.parquet`.
+fn output(dir: &Path, feed: &str) -> PathBuf {
+    dir.join(format!("{feed}.parquet"))
+}
+
+/// The snapshot's `last_updated`, in milliseconds since the epoch, for
+/// every one of its `rows`.
+fn times(last_updated: i64, rows: usize) -> Result<ArrayRef> {
+    let time =
+        epoch_millis(last_updated).ok_or_else(|| format!("last_updated is {last_updated}"))?;
+    Ok(Arc::new(TimestampMillisecondArray::from(vec![time; rows])))
+}
+
+fn times_field() -> Field {
+    Field::new(
+        "times",
+        DataType::Timestamp(TimeUnit::Millisecond, None),
+        false,
+    )
+}
+
+fn station_information(json: &[u8]) -> Result<RecordBatch> {
+    let document: Document<StationInformation> = serde_json::from_slice(json)?;
+    let stations = document.data.stations;
+    let schema = Schema::new(vec![
+        times_field(),
+        Field::new("station_id", DataType::Utf8, false),
+        Field::new("name", DataType::Utf8, false),
+        Field::new("lat", DataType::Float64, false),
+        Field::new("lon", DataType::Float64, false),
+        Field::new("capacity", DataType::Int64, true),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        times(document.last_updated, stations.len())?,
+        Arc::new(StringArray::from_iter_values(
+            stations.iter().map(|station| &station.station_id),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            stations.iter().map(|station| &station.name),
+        )),
+        Arc::new(Float64Array::from_iter_values(
+            stations.iter().map(|station| station.lat),
+        )),
+        Arc::new(Float64Array::from_iter_values(
+            stations.iter().map(|station| station.lon),
+        )),
+        Arc::new(Int64Array::from_iter(
+            stations.iter().map(|station| station.capacity),
+        )),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+fn free_bike_status(json: &[u8]) -> Result<RecordBatch> {
+    let document: Document<FreeBikeStatus> = serde_json::from_slice(json)?;
+    let bikes = document.data.bikes;
+    let schema = Schema::new(vec![
+        times_field(),
+        Field::new("bike_id", DataType::Utf8, false),
+        Field::new("lat", DataType::Float64, true),
+        Field::new("lon", DataType::Float64, true),
+        Field::new("is_reserved", DataType::Boolean, false),
+        Field::new("is_disabled", DataType::Boolean, false),
+        Field::new("vehicle_type_id", DataType::Utf8, true),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        times(document.last_updated, bikes.len())?,
+        Arc::new(StringArray::from_iter_values(
+            bikes.iter().map(|bike| &bike.bike_id),
+        )),
+        Arc::new(Float64Array::from_iter(bikes.iter().map(|bike| bike.lat))),
+        Arc::new(Float64Array::from_iter(bikes.iter().map(|bike| bike.lon))),
+        Arc::new(BooleanArray::from_iter(
+            bikes.iter().map(|bike| Some(bike.is_reserved.get())),
+        )),
+        Arc::new(BooleanArray::from_iter(
+            bikes.iter().map(|bike| Some(bike.is_disabled.get())),
+        )),
+        Arc::new(StringArray::from_iter(
+            bikes.iter().map(|bike| bike.vehicle_type_id.as_deref()),
+        )),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+/// The `--other-feeds-dir` outputs, one per feed in `FEED_OUTPUTS`, each
+/// written as `.partial` and moved into place by `finish`. A feed with no
+/// snapshots among the inputs gets no file.
+pub(crate) struct FeedOutputs<'a> {
+    args: &'a Args,
+    dir: &'a Path,
+    writers: BTreeMap<&'static str, (ArrowWriter<File>, usize)>,
+}
+
+impl<'a> FeedOutputs<'a> {
+    pub fn new(args: &'a Args, dir: &'a Path) -> Result<Self> {
+        for feed in FEED_OUTPUTS {
+            refuse_overwrite(args, &output(dir, feed))?;
+        }
+        fs::create_dir_all(dir)?;
+        Ok(FeedOutputs {
+            args,
+            dir,
+            writers: BTreeMap::new(),
+        })
+    }
+
+    /// Appends a snapshot's rows to its feed's output.
+    pub fn write(&mut self, snapshot: &OtherFeed) -> Result<()> {
+        let batch = match snapshot.feed {
+            "station_information" => station_information(&snapshot.json)?,
+            "free_bike_status" => free_bike_status(&snapshot.json)?,
+            feed => return Err(format!("--other-feeds-dir doesn't write {feed}").into()),
+        };
+        let (writer, rows) = match self.writers.get_mut(snapshot.feed) {
+            Some(writer) => writer,
+            None => {
+                let codec = match self.args.compression {
+                    Codec::Auto => Codec::Snappy,
+                    codec => codec,
+                };
+                let props = properties(
+                    codec,
+                    self.args.parquet_version,
+                    self.args.row_group_size,
+                    None,
+                );
+                let file = File::create(partial_path(&output(self.dir, snapshot.feed)))?;
+                let writer = ArrowWriter::try_new(file, batch.schema(), Some(props))?;
+                self.writers.entry(snapshot.feed).or_insert((writer, 0))
+            }
+        };
+        writer.write(&batch)?;
+        *rows += batch.num_rows();
+        Ok(())
+    }
+
+    /// Finishes every output, returning the rows written to each.
+    pub fn finish(self) -> Result<BTreeMap<String, usize>> {
+        let mut written = BTreeMap::new();
+        for (feed, (writer, rows)) in self.writers {
+            // Unlike close, this flushes the writer's buffer rather than
+            // dropping it, so the footer failing to write is an error.
+            writer.into_inner()?;
+            let output = output(self.dir, feed);
+            fs::rename(partial_path(&output), &output)?;
+            written.insert(feed.to_string(), rows);
+        }
+        Ok(written)
+    }
+}
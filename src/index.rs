@@ -0,0 +1,58 @@
+use parquet::file::metadata::RowGroupMetaData;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::path::Path;
+
+/// Which row groups of the output each station has rows in, so a reader
+/// can fetch only those with range requests.
+#[derive(Debug, Default, Serialize)]
+pub struct RowGroupIndex {
+    /// Every row group in file order, where it is and how many rows it has.
+    row_groups: Vec<RowGroupRange>,
+    /// Indices into `row_groups` for each mapped station id, ascending.
+    stations: BTreeMap<u16, Vec<usize>>,
+    /// Stations in the row group being buffered.
+    #[serde(skip)]
+    pending: BTreeSet<u16>,
+}
+
+#[derive(Debug, Serialize)]
+struct RowGroupRange {
+    /// Bytes from the start of the file to the row group's first column chunk.
+    offset: u64,
+    /// Bytes from `offset` to the end of its last column chunk.
+    length: u64,
+    rows: i64,
+}
+
+impl RowGroupIndex {
+    /// Notes a station with a row in the row group being buffered.
+    pub fn add_station(&mut self, station_id: u16) {
+        self.pending.insert(station_id);
+    }
+
+    /// Files the buffered stations under the row group just written.
+    pub fn add_row_group(&mut self, metadata: &RowGroupMetaData) {
+        let ranges = metadata.columns().iter().map(|column| column.byte_range());
+        let start = ranges.clone().map(|(start, _)| start).min().unwrap_or(0);
+        let end = ranges
+            .map(|(start, length)| start + length)
+            .max()
+            .unwrap_or(0);
+        let row_group = self.row_groups.len();
+        self.row_groups.push(RowGroupRange {
+            offset: start,
+            length: end - start,
+            rows: metadata.num_rows(),
+        });
+        for station_id in std::mem::take(&mut self.pending) {
+            self.stations.entry(station_id).or_default().push(row_group);
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        serde_json::to_writer(File::create(path)?, self)?;
+        Ok(())
+    }
+}
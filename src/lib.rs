@@ -0,0 +1,29 @@
+pub mod args;
+pub mod collector;
+pub mod compact;
+mod compression;
+mod duckdb_sink;
+pub mod export;
+mod feeds;
+mod index;
+pub mod merge;
+pub mod preflight;
+pub mod profile;
+mod rolling;
+pub mod row;
+mod sample;
+mod skew;
+pub mod snapshot;
+pub mod split;
+#[cfg(test)]
+mod test_fixtures;
+pub mod transform;
+mod vehicle_types;
+pub mod verify;
+pub mod warnings;
+mod window;
+
+pub use crate::args::Args;
+pub use crate::collector::{collect, Collector};
+
+pub type Result<T, E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
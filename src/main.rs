@@ -1,162 +1,338 @@
-use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
-use arrow::ipc::Timestamp;
-use arrow_array::builder::PrimitiveBuilder;
-use arrow_array::types::{TimestampMillisecondType, TimestampSecondType, UInt16Type};
-use arrow_array::{
-    ArrayRef, Date64Array, RecordBatch, Time64MicrosecondArray, TimestampMillisecondArray,
-    TimestampSecondArray,
-};
-use chrono::{NaiveDateTime, Timelike};
-use duckdb::{params, Connection, Result};
-use flate2::bufread;
-use glob::glob;
-use parquet::arrow::ArrowWriter;
-use parquet::basic::{Compression, Encoding};
-use parquet::file::properties::WriterProperties;
-use parquet::schema::types::ColumnPath;
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::fs;
+use bike_1::args::{Args, Command, LogFormat};
+use bike_1::collector::{check_overwrite, collect, StationFilter, Summary};
+use bike_1::compact::compact;
+use bike_1::export::export_snapshot;
+use bike_1::merge::merge_id_maps;
+use bike_1::preflight::preflight;
+use bike_1::row::schema;
+use bike_1::snapshot::{for_each_snapshot, for_each_time};
+use bike_1::split::split_by_station;
+use bike_1::verify::{check_consistency, null_counts};
+use bike_1::Result;
+use chrono::NaiveDateTime;
+use clap::Parser;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{BufReader, Write};
-use std::sync::Arc;
+use std::path::Path;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 
-#[derive(Debug, Deserialize)]
-struct Station {
-    #[serde(skip)]
-    legacy_id: String,
-    last_reported: u64,
-    num_ebikes_available: u16,
-    num_bikes_available: u16,
-    is_returning: u32,
-    #[serde(skip)]
-    eightd_has_available_keys: bool,
-    num_docks_available: u16,
-    num_docks_disabled: u16,
-    is_installed: u32,
-    num_bikes_disabled: u16,
-    station_id: String,
-    station_status: String,
-    is_renting: u32,
+/// Logs go to stdout like the original `println!`s. `RUST_LOG` is honored
+/// unless `--quiet` is passed, which only lets errors through.
+fn init_logging(args: &Args) {
+    let filter = if args.quiet {
+        EnvFilter::new("error")
+    } else if args.verbose {
+        EnvFilter::new("debug")
+    } else {
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
+    };
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match args.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct Data {
-    stations: Vec<Station>,
+/// Logs what a run would read and write without touching the filesystem.
+/// Fails if the run would clobber an existing output without `--overwrite`.
+fn dry_run(args: &Args) -> Result<()> {
+    for field in schema(&args.columns()).fields() {
+        info!(
+            name = field.name(),
+            data_type = %field.data_type(),
+            nullable = field.is_nullable(),
+            "column"
+        );
+    }
+    let filter = StationFilter::from_args(args)?;
+    let mut files = 0;
+    let mut estimated_rows = 0;
+    for_each_snapshot(args, |name, snapshot| {
+        info!(file = name, "would process");
+        files += 1;
+        if let Ok(snapshot) = snapshot {
+            estimated_rows += snapshot
+                .status
+                .data
+                .stations
+                .into_iter()
+                .map(|mut station| {
+                    station.strip_id_prefix(args.strip_id_prefix.as_deref());
+                    station
+                })
+                .filter(|station| station.station_status == "active")
+                .filter(|station| filter.keeps(&station.station_id))
+                .count();
+        }
+        Ok(())
+    })?;
+    info!(
+        files,
+        estimated_rows,
+        output = %args.output.display(),
+        id_map = %args.id_map.display(),
+        "dry run"
+    );
+    check_overwrite(args)
 }
 
-#[derive(Debug, Deserialize)]
-struct StationStatus {
-    data: Data,
-    last_updated: i64,
-    #[serde(skip)]
-    ttl: u32,
+/// Logs the time span the inputs cover and how many snapshots are in it.
+fn time_span(args: &Args) -> Result<()> {
+    let mut snapshots = 0;
+    let mut span: Option<(i64, i64)> = None;
+    for_each_time(args, |name, time| {
+        match time {
+            Ok(time) => {
+                snapshots += 1;
+                span = Some(span.map_or((time, time), |(earliest, latest)| {
+                    (earliest.min(time), latest.max(time))
+                }));
+            }
+            Err(err) => warn!(file = name, error = %err, "skipping unreadable snapshot"),
+        }
+        Ok(())
+    })?;
+    match span {
+        Some((earliest, latest)) => {
+            let earliest = NaiveDateTime::from_timestamp_millis(earliest).unwrap();
+            let latest = NaiveDateTime::from_timestamp_millis(latest).unwrap();
+            info!(snapshots, %earliest, %latest, "time span");
+        }
+        None => info!(snapshots, "no readable snapshots"),
+    }
+    Ok(())
 }
 
 fn main() {
-    // let conn = Connection::open("data.duckdb").unwrap();
-
-    let file = File::create("data.parquet").unwrap();
-
-    let station_ids = Field::new("station_ids", DataType::UInt16, false);
-    let num_bikes_available = Field::new("num_bikes_available", DataType::UInt16, false);
-    let num_ebikes_available = Field::new("num_ebikes_available", DataType::UInt16, false);
-    let num_docks_available = Field::new("num_docks_available", DataType::UInt16, false);
-    let num_bikes_disabled = Field::new("num_bikes_disabled", DataType::UInt16, false);
-    let times = Field::new(
-        "times",
-        DataType::Timestamp(TimeUnit::Millisecond, None),
-        false,
-    );
-
-    let mut id_legend: HashMap<String, u16> = HashMap::new();
-    let mut id_counter: u16 = 0;
-
-    let schema = Schema::new(vec![
-        station_ids,
-        num_bikes_available,
-        num_ebikes_available,
-        num_bikes_disabled,
-        num_docks_available,
-        times,
-    ]);
-
-    let props = WriterProperties::builder().build();
-
-    let mut writer = ArrowWriter::try_new(file, schema.into(), props.into()).unwrap();
-
-    // Warning: You can specify Second here, and it won't work!
-    // https://github.com/apache/arrow-rs/issues/1920#issuecomment-1164220176
-    let mut times = PrimitiveBuilder::<TimestampMillisecondType>::new();
-    let mut station_ids = PrimitiveBuilder::<UInt16Type>::new();
-    let mut num_bikes_available = PrimitiveBuilder::<UInt16Type>::new();
-    let mut num_ebikes_available = PrimitiveBuilder::<UInt16Type>::new();
-    let mut num_bikes_disabled = PrimitiveBuilder::<UInt16Type>::new();
-    let mut num_docks_available = PrimitiveBuilder::<UInt16Type>::new();
-
-    for entry in glob("./station_status/*.json.gz").expect("Failed to read glob pattern") {
-        println!("Processing {:?}", entry);
-        let input = BufReader::new(File::open(entry.unwrap()).unwrap());
-        let mut decoder = bufread::GzDecoder::new(input);
-        let status: StationStatus = serde_json::from_reader(&mut decoder).unwrap();
-        let time = NaiveDateTime::from_timestamp_opt(status.last_updated, 0)
-            .unwrap()
-            .with_second(0)
-            .unwrap();
-        let stations: Vec<Station> = status
-            .data
-            .stations
-            .into_iter()
-            .filter(|station| station.station_status == "active")
-            .collect();
+    let args = Args::parse();
+    init_logging(&args);
+    if let Err(err) = run(&args) {
+        error!("{err}");
+        std::process::exit(1);
+    }
+}
 
-        for station in &stations {
-            times.append_value(time.timestamp_millis());
-            let station_id = id_legend
-                .entry(station.station_id.clone().into())
-                .or_insert_with(|| {
-                    id_counter = id_counter + 1;
-                    id_counter
-                });
-            station_ids.append_value(*station_id);
-            num_bikes_available
-                .append_value(station.num_bikes_available - station.num_ebikes_available);
-            num_bikes_disabled.append_value(station.num_bikes_disabled);
-            num_ebikes_available.append_value(station.num_ebikes_available);
-            num_docks_available.append_value(station.num_docks_available);
+fn run(args: &Args) -> Result<()> {
+    if args.fast_json && cfg!(not(feature = "simd")) {
+        warn!("--fast-json needs the `simd` feature, using serde_json");
+    }
+    match &args.command {
+        Some(Command::Info { path }) => return info(args.log_format, path),
+        Some(Command::Verify { path }) => return verify(args.log_format, path),
+        Some(Command::CheckConsistency { parquet }) => {
+            return consistency(args.log_format, parquet, &args.id_map)
+        }
+        Some(Command::Compact {
+            input,
+            output,
+            target_row_group_size,
+            delete_inputs,
+        }) => {
+            let rows = compact(args, input, output, *target_row_group_size, *delete_inputs)?;
+            info!(rows, output = %output.display(), "compacted");
+            return Ok(());
+        }
+        Some(Command::SplitByStation {
+            path,
+            out_dir,
+            max_files_open,
+        }) => {
+            let stations = split_by_station(args, path, out_dir, *max_files_open)?;
+            info!(stations, out_dir = %out_dir.display(), "split");
+            return Ok(());
+        }
+        Some(Command::ExportSnapshot { path, time, out }) => {
+            let (time, stations) = export_snapshot(args, path, *time, out)?;
+            let time = NaiveDateTime::from_timestamp_millis(time).unwrap();
+            info!(%time, stations, out = %out.display(), "exported");
+            return Ok(());
         }
+        Some(Command::MergeIdMaps {
+            inputs,
+            output,
+            remap_out,
+            namespace_by_system,
+        }) => {
+            let stations = merge_id_maps(args, inputs, output, remap_out, *namespace_by_system)?;
+            info!(
+                stations,
+                inputs = inputs.len(),
+                output = %output.display(),
+                remap_out = %remap_out.display(),
+                "merged id maps"
+            );
+            return Ok(());
+        }
+        None => {}
     }
+    if args.dry_run {
+        return dry_run(args);
+    }
+    if args.time_span {
+        return time_span(args);
+    }
+    if args.preflight {
+        preflight(args)?;
+    }
+    let summary = collect(args)?;
+    if !args.quiet {
+        print_summary(args.log_format, &summary)?;
+    }
+    if args.fail_on_warning && summary.warnings > 0 {
+        return Err(format!("{} warning(s) under --fail-on-warning", summary.warnings).into());
+    }
+    Ok(())
+}
 
-    let batch = RecordBatch::try_from_iter(vec![
-        ("station_ids", Arc::new(station_ids.finish()) as ArrayRef),
-        (
-            "num_bikes_available",
-            Arc::new(num_bikes_available.finish()) as ArrayRef,
-        ),
-        (
-            "num_ebikes_available",
-            Arc::new(num_ebikes_available.finish()) as ArrayRef,
-        ),
-        (
-            "num_bikes_disabled",
-            Arc::new(num_bikes_disabled.finish()) as ArrayRef,
-        ),
-        (
-            "num_docks_available",
-            Arc::new(num_docks_available.finish()) as ArrayRef,
-        ),
-        ("time", Arc::new(times.finish()) as ArrayRef),
-    ])
-    .unwrap();
-
-    writer.write(&batch).expect("Writing batch");
+fn print_summary(format: LogFormat, summary: &Summary) -> Result<()> {
+    match format {
+        LogFormat::Text => {
+            info!(
+                files = summary.files,
+                rows = summary.rows,
+                stations = summary.stations,
+                duplicate_snapshots = summary.duplicate_snapshots,
+                skipped_old_snapshots = summary.skipped_old_snapshots,
+                degenerate_snapshots = summary.degenerate_snapshots,
+                backwards_snapshots = summary.backwards_snapshots,
+                near_duplicate_rows = summary.near_duplicate_rows,
+                warnings = summary.warnings,
+                skipped_files = summary.skipped_files,
+                row_groups = summary.row_groups,
+                rewritten_ids = summary.rewritten_ids,
+                duckdb_inserted = summary.duckdb_inserted,
+                "done"
+            );
+            for timing in &summary.slowest_files {
+                info!(
+                    file = %timing.file,
+                    decompress_ms = timing.decompress_ms,
+                    parse_ms = timing.parse_ms,
+                    stations = timing.stations,
+                    "slow file"
+                );
+            }
+            for (status, stations) in &summary.inactive_stations {
+                info!(%status, stations, "left out stations that weren't active");
+            }
+            for (feed, rows) in &summary.other_feed_rows {
+                info!(feed, rows, "wrote another feed's snapshots");
+            }
+            if let Some(profile) = &summary.profile {
+                for (phase, ms) in profile.phases() {
+                    info!(
+                        phase,
+                        ms,
+                        percent = 100.0 * ms / profile.total_ms,
+                        "profile"
+                    );
+                }
+            }
+        }
+        LogFormat::Json => println!("{}", serde_json::to_string(summary)?),
+    }
+    Ok(())
+}
 
-    println!("Done!");
-    // writer must be closed to write footer
-    writer.close().unwrap();
+/// Logs an output's row count and key-value metadata, leaving out the
+/// embedded arrow schema.
+fn info(format: LogFormat, path: &Path) -> Result<()> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    let metadata = reader.metadata().file_metadata();
+    let kvs: BTreeMap<&str, &str> = metadata
+        .key_value_metadata()
+        .into_iter()
+        .flatten()
+        .filter(|kv| kv.key != "ARROW:schema")
+        .map(|kv| (kv.key.as_str(), kv.value.as_deref().unwrap_or("")))
+        .collect();
+    match format {
+        LogFormat::Text => {
+            info!(
+                file = %path.display(),
+                rows = metadata.num_rows(),
+                row_groups = reader.num_row_groups(),
+                "info"
+            );
+            for (key, value) in &kvs {
+                info!(key, value, "metadata");
+            }
+        }
+        LogFormat::Json => println!("{}", serde_json::to_string(&kvs)?),
+    }
+    Ok(())
+}
 
-    let mut file = File::create("id_map.json").unwrap();
+/// Logs the output's ids missing from the id map, the ids the map gives
+/// several stations and the mapped stations absent from the output, and
+/// fails if the output and the map disagree on the first two.
+fn consistency(format: LogFormat, parquet: &Path, id_map: &Path) -> Result<()> {
+    let consistency = check_consistency(parquet, id_map)?;
+    match format {
+        LogFormat::Text => {
+            for (id, station_id) in &consistency.absent_stations {
+                info!(id, station_id, "in the id map but not the output");
+            }
+            for (id, station_ids) in &consistency.shared_ids {
+                warn!(
+                    id,
+                    station_ids = station_ids.join(", "),
+                    "id shared by stations"
+                );
+            }
+            for id in &consistency.unmapped_ids {
+                warn!(id, "in the output but not the id map");
+            }
+            info!(
+                stations = consistency.stations,
+                absent_stations = consistency.absent_stations.len(),
+                "checked"
+            );
+        }
+        LogFormat::Json => println!("{}", serde_json::to_string(&consistency)?),
+    }
+    if !consistency.is_consistent() {
+        return Err(format!(
+            "{} and {} disagree: {} id(s) missing from the map, {} shared by several stations",
+            parquet.display(),
+            id_map.display(),
+            consistency.unmapped_ids.len(),
+            consistency.shared_ids.len()
+        )
+        .into());
+    }
+    Ok(())
+}
 
-    let serialized_data = serde_json::to_string(&id_legend).unwrap();
-    file.write_all(serialized_data.as_bytes()).unwrap();
+fn verify(format: LogFormat, path: &Path) -> Result<()> {
+    let counts = null_counts(path)?;
+    match format {
+        LogFormat::Text => {
+            for count in &counts {
+                info!(
+                    column = count.column,
+                    nulls = count.nulls,
+                    required = count.required,
+                    "nulls"
+                );
+            }
+        }
+        LogFormat::Json => println!("{}", serde_json::to_string(&counts)?),
+    }
+    let violations: Vec<String> = counts
+        .iter()
+        .filter(|count| count.is_violation())
+        .map(|count| format!("{} ({} nulls)", count.column, count.nulls))
+        .collect();
+    if !violations.is_empty() {
+        return Err(format!(
+            "{}: nulls in columns that are never null: {}",
+            path.display(),
+            violations.join(", ")
+        )
+        .into());
+    }
+    Ok(())
 }
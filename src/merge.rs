@@ -0,0 +1,162 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::collector::{read_id_map, refuse_overwrite, SortedIdMap};
+use crate::{Args, Result};
+
+/// Combines the id maps at `inputs` into one at `output`, numbering every
+/// station afresh from 1 in input order and each input's own id order, so
+/// no two stations share an id. `remap_out` gets, for each input, a table
+/// from its old ids to the new ones to rewrite its outputs with. Returns
+/// how many stations the combined map has.
+///
+/// A provider station id in more than one input is an error, since it may
+/// be two different stations. With `namespace_by_system` every id is
+/// prefixed with its system, the name of the directory its map is in, as
+/// `<system>:<station_id>`.
+pub fn merge_id_maps(
+    args: &Args,
+    inputs: &[PathBuf],
+    output: &Path,
+    remap_out: &Path,
+    namespace_by_system: bool,
+) -> Result<usize> {
+    for path in [output, remap_out] {
+        refuse_overwrite(args, path)?;
+    }
+
+    let mut merged: HashMap<String, u16> = HashMap::new();
+    // Which input each combined station id came from, for the error.
+    let mut sources: HashMap<String, &Path> = HashMap::new();
+    let mut systems: HashMap<String, &Path> = HashMap::new();
+    let mut remaps: BTreeMap<String, BTreeMap<u16, u16>> = BTreeMap::new();
+    for input in inputs {
+        let legend = read_id_map(input)?;
+        let prefix = if namespace_by_system {
+            let system = system(input)?;
+            if let Some(other) = systems.insert(system.clone(), input) {
+                return Err(format!(
+                    "{} and {} are both in a directory named {system}",
+                    other.display(),
+                    input.display()
+                )
+                .into());
+            }
+            format!("{system}:")
+        } else {
+            String::new()
+        };
+
+        let mut stations: Vec<(u16, String)> =
+            legend.into_iter().map(|(name, id)| (id, name)).collect();
+        stations.sort_unstable();
+        let remap = remaps.entry(input.display().to_string()).or_default();
+        for (old, name) in stations {
+            let name = format!("{prefix}{name}");
+            if let Some(other) = sources.insert(name.clone(), input) {
+                return Err(format!(
+                    "station id {name} is in both {} and {} (pass --namespace-by-system to keep them apart)",
+                    other.display(),
+                    input.display()
+                )
+                .into());
+            }
+            let new = u16::try_from(merged.len() + 1)
+                .map_err(|_| "the combined id map has more stations than fit a u16")?;
+            merged.insert(name, new);
+            remap.insert(old, new);
+        }
+    }
+
+    let mut file = BufWriter::new(File::create(output)?);
+    if args.pretty_id_map {
+        let sorted = SortedIdMap(
+            merged
+                .iter()
+                .map(|(name, id)| (*id, name.as_str()))
+                .collect(),
+        );
+        serde_json::to_writer_pretty(&mut file, &sorted)?;
+    } else {
+        serde_json::to_writer(&mut file, &merged)?;
+    }
+    file.flush()?;
+    let mut file = BufWriter::new(File::create(remap_out)?);
+    serde_json::to_writer_pretty(&mut file, &remaps)?;
+    file.flush()?;
+    Ok(merged.len())
+}
+
+/// The name of the directory an id map is in.
+fn system(input: &Path) -> Result<String> {
+    input
+        .canonicalize()?
+        .parent()
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("{} isn't in a directory", input.display()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use serde_json::json;
+    use std::fs;
+
+    use super::*;
+    use crate::test_fixtures::{read_json, TempDir};
+
+    /// Writes `map` to `<system>/id_map.json` under `dir`.
+    fn write_map(dir: &TempDir, system: &str, map: serde_json::Value) -> PathBuf {
+        fs::create_dir_all(dir.join(system)).unwrap();
+        let path = dir.join(format!("{system}/id_map.json"));
+        fs::write(&path, map.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn overlapping_ids_are_renumbered_apart() {
+        let dir = TempDir::new();
+        let inputs = [
+            write_map(&dir, "nyc", json!({ "A": 1, "B": 2 })),
+            write_map(&dir, "dc", json!({ "C": 2, "D": 1 })),
+        ];
+        let (output, remap_out) = (dir.join("merged.json"), dir.join("remap.json"));
+        let args = Args::parse_from(["bike-1"]);
+        assert_eq!(
+            merge_id_maps(&args, &inputs, &output, &remap_out, false).unwrap(),
+            4
+        );
+        assert_eq!(
+            read_json(&output),
+            json!({ "A": 1, "B": 2, "D": 3, "C": 4 })
+        );
+        assert_eq!(
+            read_json(&remap_out),
+            json!({
+                inputs[0].display().to_string(): { "1": 1, "2": 2 },
+                inputs[1].display().to_string(): { "1": 3, "2": 4 },
+            })
+        );
+    }
+
+    #[test]
+    fn a_station_in_two_maps_needs_namespacing() {
+        let dir = TempDir::new();
+        let inputs = [
+            write_map(&dir, "nyc", json!({ "A": 1 })),
+            write_map(&dir, "dc", json!({ "A": 1 })),
+        ];
+        let (output, remap_out) = (dir.join("merged.json"), dir.join("remap.json"));
+        let args = Args::parse_from(["bike-1"]);
+        let err = merge_id_maps(&args, &inputs, &output, &remap_out, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("--namespace-by-system"), "{err}");
+
+        merge_id_maps(&args, &inputs, &output, &remap_out, true).unwrap();
+        assert_eq!(read_json(&output), json!({ "nyc:A": 1, "dc:A": 2 }));
+    }
+}
@@ -0,0 +1,120 @@
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::snapshot::input_paths;
+use crate::{Args, Result};
+
+/// Checks, before any input is read, that a file can be made and removed
+/// in the output's directory, and warns when the inputs take up more bytes
+/// than that directory's filesystem has free. The output has so far come
+/// out well under the gzipped inputs' size, so it stands in for the
+/// output's.
+pub fn preflight(args: &Args) -> Result<()> {
+    let dir = match args.output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut probe = args.output.clone().into_os_string();
+    probe.push(".preflight");
+    // Truncating rather than `create_new`, so a probe left by a killed run
+    // doesn't read as an unwritable directory.
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe)
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|err| format!("preflight: can't write to {}: {err}", dir.display()))?;
+
+    let (Some(input_bytes), Some(free_bytes)) = (input_bytes(args), free_bytes(dir)) else {
+        info!(dir = %dir.display(), "preflight passed, free space unchecked");
+        return Ok(());
+    };
+    if input_bytes > free_bytes {
+        warn!(
+            dir = %dir.display(),
+            input_bytes,
+            free_bytes,
+            "the inputs are bigger than the free space left for the output"
+        );
+    } else {
+        info!(dir = %dir.display(), input_bytes, free_bytes, "preflight passed");
+    }
+    Ok(())
+}
+
+/// The total size of the input files or the `--input-tar` archive, if
+/// every one can be looked up.
+fn input_bytes(args: &Args) -> Option<u64> {
+    if let Some(tar_path) = &args.input_tar {
+        return Some(fs::metadata(tar_path).ok()?.len());
+    }
+    input_paths(args)
+        .ok()?
+        .into_iter()
+        .map(|path| Some(fs::metadata(path).ok()?.len()))
+        .sum()
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `dir`.
+#[cfg(unix)]
+fn free_bytes(dir: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated, and `stat` is only read once
+    // statvfs has filled it in.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return None;
+        }
+        stat.assume_init()
+    };
+    // Both are narrower than u64 on some platforms.
+    #[allow(clippy::useless_conversion)]
+    Some(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+}
+
+#[cfg(not(unix))]
+fn free_bytes(_dir: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use std::fs;
+
+    use super::*;
+    use crate::test_fixtures::{station, TempDir, T0};
+
+    #[test]
+    fn a_stale_probe_is_replaced_and_removed() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let args = Args::parse_from(dir.collect_argv(&["--preflight"]));
+        let probe = dir.join("data.parquet.preflight");
+        fs::write(&probe, "left by a killed run").unwrap();
+        preflight(&args).unwrap();
+        assert!(!probe.exists());
+    }
+
+    #[test]
+    fn an_unwritable_directory_fails() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let mut args = Args::parse_from(dir.collect_argv(&["--preflight"]));
+        // Root can write under any mode bits, but nobody can make a file
+        // under a regular file.
+        let not_a_dir = dir.join("not_a_dir");
+        fs::write(&not_a_dir, "").unwrap();
+        args.output = not_a_dir.join("data.parquet");
+        let err = preflight(&args).unwrap_err().to_string();
+        let expected = format!("preflight: can't write to {}", not_a_dir.display());
+        assert!(err.starts_with(&expected), "{err}");
+    }
+}
@@ -0,0 +1,99 @@
+use serde::Serialize;
+use std::time::Instant;
+
+/// A part of collecting that `--profile` times.
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    /// Reading and un-gzipping snapshots.
+    Decompress,
+    /// Deserializing snapshot JSON.
+    Parse,
+    /// Mapping provider station ids to integer ids.
+    IdLookup,
+    /// Appending rows to the column builders.
+    Append,
+    /// Writing row groups, the footer, the id map and DuckDB rows.
+    Flush,
+}
+
+/// Where a run's time went, cumulative over every snapshot, in milliseconds.
+/// Time outside every phase is `other_ms`, so the phases add up to
+/// `total_ms`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Profile {
+    pub decompress_ms: f64,
+    pub parse_ms: f64,
+    pub id_lookup_ms: f64,
+    pub append_ms: f64,
+    pub flush_ms: f64,
+    pub other_ms: f64,
+    pub total_ms: f64,
+}
+
+impl Profile {
+    /// Each phase's name and time, `other` last.
+    pub fn phases(&self) -> [(&'static str, f64); 6] {
+        [
+            ("decompress", self.decompress_ms),
+            ("parse", self.parse_ms),
+            ("id_lookup", self.id_lookup_ms),
+            ("append", self.append_ms),
+            ("flush", self.flush_ms),
+            ("other", self.other_ms),
+        ]
+    }
+}
+
+/// Accumulates a [`Profile`] from when it's created, or does nothing when
+/// profiling is off.
+#[derive(Debug)]
+pub(crate) struct Profiler {
+    started: Option<Instant>,
+    profile: Profile,
+}
+
+impl Profiler {
+    pub fn new(enabled: bool) -> Self {
+        Profiler {
+            started: enabled.then(Instant::now),
+            profile: Profile::default(),
+        }
+    }
+
+    /// The start of a phase, to pass to `stop`; `None` when profiling is off
+    /// so the clock isn't read.
+    pub fn start(&self) -> Option<Instant> {
+        self.started.map(|_| Instant::now())
+    }
+
+    pub fn stop(&mut self, phase: Phase, started: Option<Instant>) {
+        if let Some(started) = started {
+            self.add(phase, started.elapsed().as_secs_f64() * 1000.0);
+        }
+    }
+
+    /// Adds time measured elsewhere.
+    pub fn add(&mut self, phase: Phase, ms: f64) {
+        if self.started.is_none() {
+            return;
+        }
+        let bucket = match phase {
+            Phase::Decompress => &mut self.profile.decompress_ms,
+            Phase::Parse => &mut self.profile.parse_ms,
+            Phase::IdLookup => &mut self.profile.id_lookup_ms,
+            Phase::Append => &mut self.profile.append_ms,
+            Phase::Flush => &mut self.profile.flush_ms,
+        };
+        *bucket += ms;
+    }
+
+    pub fn finish(&self) -> Option<Profile> {
+        let total_ms = self.started?.elapsed().as_secs_f64() * 1000.0;
+        let phases: f64 = self.profile.phases().iter().map(|(_, ms)| ms).sum();
+        Some(Profile {
+            other_ms: (total_ms - phases).max(0.0),
+            total_ms,
+            ..self.profile.clone()
+        })
+    }
+}
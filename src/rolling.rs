@@ -0,0 +1,143 @@
+use chrono::Duration;
+use std::collections::{HashMap, VecDeque};
+
+use crate::args::RollingGap;
+
+/// Each station's `occupancy_rolling_mean`, for `--rolling-window`. Rows
+/// come in per station in time order, as snapshots are collected.
+pub(crate) struct RollingOccupancy {
+    window: i64,
+    max_gap: i64,
+    on_gap: RollingGap,
+    stations: HashMap<u16, Window>,
+}
+
+#[derive(Default)]
+struct Window {
+    /// Times and occupancies of the station's rows inside the window.
+    rows: VecDeque<(i64, Option<f64>)>,
+    last: Option<i64>,
+    /// The first row after the latest gap, under `RollingGap::Null`.
+    after_gap: Option<i64>,
+}
+
+impl RollingOccupancy {
+    pub fn new(window: Duration, max_gap: Duration, on_gap: RollingGap) -> Self {
+        RollingOccupancy {
+            window: window.num_milliseconds(),
+            max_gap: max_gap.num_milliseconds(),
+            on_gap,
+            stations: HashMap::new(),
+        }
+    }
+
+    /// Adds a row, `time` in milliseconds, returning the mean occupancy of
+    /// the station's rows in the window ending at it, nulls left out. A row
+    /// more than the maximum gap after the station's last, or before it,
+    /// starts the mean over or makes it null until the window is past the
+    /// gap.
+    pub fn add(
+        &mut self,
+        station_id: u16,
+        time: i64,
+        bikes: Option<u16>,
+        docks: Option<u16>,
+    ) -> Option<f64> {
+        let occupancy = match bikes.zip(docks) {
+            Some((bikes, docks)) if bikes > 0 || docks > 0 => {
+                Some(f64::from(bikes) / (f64::from(bikes) + f64::from(docks)))
+            }
+            _ => None,
+        };
+        let station = self.stations.entry(station_id).or_default();
+        if let Some(last) = station.last {
+            if time < last || time - last > self.max_gap {
+                match self.on_gap {
+                    RollingGap::Reset => station.rows.clear(),
+                    RollingGap::Null => station.after_gap = Some(time),
+                }
+            }
+        }
+        station.last = Some(time);
+        station.rows.push_back((time, occupancy));
+        while station
+            .rows
+            .front()
+            .is_some_and(|(oldest, _)| *oldest <= time - self.window)
+        {
+            station.rows.pop_front();
+        }
+        if station
+            .after_gap
+            .is_some_and(|after_gap| time - self.window < after_gap)
+        {
+            return None;
+        }
+        let (sum, count) = station
+            .rows
+            .iter()
+            .filter_map(|(_, occupancy)| *occupancy)
+            .fold((0.0, 0), |(sum, count), occupancy| {
+                (sum + occupancy, count + 1)
+            });
+        (count > 0).then(|| sum / f64::from(count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: i64 = 60_000;
+
+    /// Adds each `(minute, bikes)` for one station with 10 bikes plus docks,
+    /// returning the means as whole percentages.
+    fn means(on_gap: RollingGap, rows: &[(i64, u16)]) -> Vec<Option<i64>> {
+        let mut rolling = RollingOccupancy::new(Duration::minutes(3), Duration::minutes(2), on_gap);
+        rows.iter()
+            .map(|&(minute, bikes)| {
+                let mean = rolling.add(1, minute * MINUTE, Some(bikes), Some(10 - bikes));
+                mean.map(|mean| (mean * 100.0).round() as i64)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_row_a_whole_window_old_is_left_out() {
+        let rows = [(0, 0), (1, 2), (2, 4), (3, 6), (4, 8)];
+        // At minute 3 the window is (0, 3], so minute 0 has just left it.
+        assert_eq!(
+            means(RollingGap::Reset, &rows),
+            [Some(0), Some(10), Some(20), Some(40), Some(60)]
+        );
+    }
+
+    #[test]
+    fn a_gap_resets_the_mean_or_nulls_it_until_the_window_is_past() {
+        // Three minutes between 1 and 4 is over the two minute gap.
+        let rows = [(0, 2), (1, 4), (4, 8), (5, 6), (6, 4), (7, 2)];
+        assert_eq!(
+            means(RollingGap::Reset, &rows),
+            [Some(20), Some(30), Some(80), Some(70), Some(60), Some(40)]
+        );
+        // The window ending at minute 7 is (4, 7], the first past the gap.
+        assert_eq!(
+            means(RollingGap::Null, &rows),
+            [Some(20), Some(30), None, None, None, Some(40)]
+        );
+    }
+
+    #[test]
+    fn a_station_with_no_bikes_or_docks_is_left_out_of_the_mean() {
+        let mut rolling = RollingOccupancy::new(
+            Duration::minutes(3),
+            Duration::minutes(2),
+            RollingGap::Reset,
+        );
+        assert_eq!(rolling.add(1, 0, Some(0), Some(0)), None);
+        assert_eq!(rolling.add(1, MINUTE, Some(5), Some(5)), Some(0.5));
+        assert_eq!(rolling.add(1, 2 * MINUTE, None, Some(5)), Some(0.5));
+        // Another station's rows are its own.
+        assert_eq!(rolling.add(2, 2 * MINUTE, Some(1), Some(3)), Some(0.25));
+    }
+}
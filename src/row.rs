@@ -0,0 +1,643 @@
+use arrow::datatypes::{DataType, Field, FieldRef, Fields, Schema, TimeUnit};
+use arrow_array::builder::{BooleanBuilder, PrimitiveBuilder, StringDictionaryBuilder};
+use arrow_array::types::{Float64Type, Int32Type, TimestampMillisecondType, UInt16Type};
+use arrow_array::{ArrayRef, DictionaryArray, Int32Array, RecordBatch, StringArray};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::mem;
+use std::sync::Arc;
+
+use crate::args::TimeRounding;
+use crate::Result;
+
+/// Stored as `schema_version` in the parquet footer. Bump it whenever the
+/// columns change so outputs from an older build are reprocessed in full.
+pub const SCHEMA_VERSION: &str = "3";
+
+/// One output row. Serializes with the schema's field names, except that
+/// `num_bikes_available` is always the feed's total; `Columns::bikes` gives
+/// the bikes column the schema actually has.
+#[derive(Debug, Clone, Serialize)]
+pub struct Row {
+    #[serde(rename = "station_ids")]
+    pub station_id: u16,
+    /// The feed's total, ebikes included. See `Columns::classic_bikes`.
+    /// Counts are null where the feed sent a sentinel; see `Station::count`.
+    pub num_bikes_available: Option<u16>,
+    pub num_ebikes_available: Option<u16>,
+    pub num_bikes_disabled: Option<u16>,
+    pub num_ebikes_disabled: Option<u16>,
+    pub num_classic_disabled: Option<u16>,
+    pub num_docks_available: Option<u16>,
+    /// Whether the station accepts returns, null if the feed sent junk.
+    pub is_returning: Option<bool>,
+    /// Milliseconds since the epoch.
+    #[serde(rename = "times")]
+    pub time: i64,
+    /// The snapshot file this row came from, with `--with-source`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+    /// When the station last reported, in milliseconds since the epoch, with
+    /// `--with-last-reported`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_reported: Option<i64>,
+    /// With `--with-8d`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eightd_has_available_keys: Option<bool>,
+    /// With `--rolling-window`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occupancy_rolling_mean: Option<f64>,
+}
+
+impl Row {
+    /// Whether both rows report the same counts and `is_returning`, whatever
+    /// their times, source files and `last_reported`.
+    pub fn same_values(&self, other: &Row) -> bool {
+        self.station_id == other.station_id
+            && self.num_bikes_available == other.num_bikes_available
+            && self.num_ebikes_available == other.num_ebikes_available
+            && self.num_bikes_disabled == other.num_bikes_disabled
+            && self.num_ebikes_disabled == other.num_ebikes_disabled
+            && self.num_classic_disabled == other.num_classic_disabled
+            && self.num_docks_available == other.num_docks_available
+            && self.is_returning == other.is_returning
+    }
+}
+
+/// Optional columns and how the rest are written, per the command line
+/// flags.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Columns {
+    pub source_file: bool,
+    /// Add `num_bikes_usable`, the feed's bikes available less disabled.
+    pub usable_bikes: bool,
+    /// Write classic bikes only (the total less ebikes) as
+    /// `num_classic_bikes_available` instead of the feed's total.
+    pub classic_bikes: bool,
+    /// Write `station_ids` as the provider's ids, dictionary encoded, rather
+    /// than the mapped integers.
+    pub station_id_dictionary: bool,
+    /// Add the stations' `last_reported` as a nullable timestamp.
+    pub last_reported: bool,
+    /// Add the stations' `eightd_has_available_keys` as a nullable boolean.
+    pub eightd_has_available_keys: bool,
+    /// Add `occupancy_rolling_mean`, worked out by the collector.
+    pub rolling_occupancy: bool,
+    /// Values as the feed sent them, under `--raw`: `times` to the second
+    /// and no disabled breakdown.
+    pub raw: bool,
+    /// How `times` is snapped to a minute, unless `raw`.
+    pub time_rounding: TimeRounding,
+}
+
+impl Columns {
+    /// The name of the bikes column, classic bikes only or the feed's total.
+    pub fn bikes_available(&self) -> &'static str {
+        if self.classic_bikes {
+            "num_classic_bikes_available"
+        } else {
+            "num_bikes_available"
+        }
+    }
+
+    /// `row`'s value for the `bikes_available` column.
+    pub fn bikes(&self, row: &Row) -> Option<u16> {
+        if self.classic_bikes {
+            row.num_bikes_available
+                .zip(row.num_ebikes_available)
+                .map(|(total, ebikes)| total.saturating_sub(ebikes))
+        } else {
+            row.num_bikes_available
+        }
+    }
+}
+
+pub fn schema(columns: &Columns) -> Schema {
+    let station_ids = if columns.station_id_dictionary {
+        Field::new(
+            "station_ids",
+            DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+            false,
+        )
+    } else {
+        Field::new("station_ids", DataType::UInt16, false)
+    };
+    let num_bikes_available = Field::new(columns.bikes_available(), DataType::UInt16, true);
+    let num_ebikes_available = Field::new("num_ebikes_available", DataType::UInt16, true);
+    let num_docks_available = Field::new("num_docks_available", DataType::UInt16, true);
+    let is_returning = Field::new("is_returning", DataType::Boolean, true);
+    let num_bikes_disabled = Field::new("num_bikes_disabled", DataType::UInt16, true);
+    let num_ebikes_disabled = Field::new("num_ebikes_disabled", DataType::UInt16, true);
+    let num_classic_disabled = Field::new("num_classic_disabled", DataType::UInt16, true);
+    let times = Field::new(
+        "times",
+        DataType::Timestamp(TimeUnit::Millisecond, None),
+        false,
+    );
+
+    let mut fields = vec![
+        station_ids,
+        num_bikes_available,
+        num_ebikes_available,
+        num_bikes_disabled,
+        num_ebikes_disabled,
+        num_classic_disabled,
+        num_docks_available,
+        is_returning,
+        times,
+    ];
+    if columns.source_file {
+        // Dictionary encoded, since every row of a snapshot repeats the name.
+        fields.push(Field::new(
+            "source_file",
+            DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+            false,
+        ));
+    }
+    if columns.usable_bikes {
+        fields.push(Field::new("num_bikes_usable", DataType::UInt16, true));
+    }
+    if columns.last_reported {
+        fields.push(Field::new(
+            "last_reported",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        ));
+    }
+    if columns.eightd_has_available_keys {
+        fields.push(Field::new(
+            "eightd_has_available_keys",
+            DataType::Boolean,
+            true,
+        ));
+    }
+    if columns.rolling_occupancy {
+        fields.push(Field::new(
+            "occupancy_rolling_mean",
+            DataType::Float64,
+            true,
+        ));
+    }
+    Schema::new(fields)
+}
+
+/// One column of the output, as written by `--columns-metadata`.
+#[derive(Debug, Serialize)]
+pub struct ColumnDescription {
+    pub name: String,
+    pub data_type: String,
+    pub nullable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<&'static str>,
+    /// Missing for columns added by a `RowTransform`.
+    pub description: Option<String>,
+}
+
+/// Describes every field of `schema`, an output schema written with
+/// `columns` and then renamed by `renames`.
+pub fn describe(
+    schema: &Schema,
+    columns: &Columns,
+    renames: &[(String, String)],
+) -> Vec<ColumnDescription> {
+    schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let original = renames
+                .iter()
+                .find(|(_, to)| to == field.name())
+                .map_or(field.name(), |(from, _)| from);
+            let (unit, description) = match original.as_str() {
+                "num_ebikes_disabled" | "num_classic_disabled" if columns.raw => (
+                    Some("bikes"),
+                    Some("Always null under --raw, which leaves vehicle_types_available alone."),
+                ),
+                "station_ids" if columns.station_id_dictionary => {
+                    (None, Some("The provider's station_id."))
+                }
+                "station_ids" => (
+                    None,
+                    Some("The station, numbered in order of first appearance; id_map.json maps it to the provider's station_id."),
+                ),
+                "num_bikes_available" => (
+                    Some("bikes"),
+                    Some("Bikes available to rent, ebikes included, as the feed reports them."),
+                ),
+                "num_classic_bikes_available" => (
+                    Some("bikes"),
+                    Some("Classic bikes available to rent: the feed's num_bikes_available less num_ebikes_available."),
+                ),
+                "num_ebikes_available" => (Some("bikes"), Some("Ebikes available to rent.")),
+                "num_bikes_disabled" => (
+                    Some("bikes"),
+                    Some("Bikes at the station that can't be rented, ebikes included."),
+                ),
+                "num_ebikes_disabled" => (
+                    Some("bikes"),
+                    Some("Disabled ebikes, the --ebike-vehicle-types entries of vehicle_types_available; null when the feed doesn't break them down."),
+                ),
+                "num_classic_disabled" => (
+                    Some("bikes"),
+                    Some("Disabled bikes less disabled ebikes, from vehicle_types_available; the feed's combined num_bikes_disabled when it doesn't break them down."),
+                ),
+                "num_docks_available" => (Some("docks"), Some("Empty docks bikes can be returned to.")),
+                "is_returning" => (None, Some("Whether the station accepts returns.")),
+                "times" if columns.raw => (
+                    Some("milliseconds since the Unix epoch, UTC"),
+                    Some("The snapshot's last_updated, to the second, as the feed sent it."),
+                ),
+                "times" => (
+                    Some("milliseconds since the Unix epoch, UTC"),
+                    Some(match columns.time_rounding {
+                        TimeRounding::Floor => "The snapshot's last_updated, rounded down to a whole minute.",
+                        TimeRounding::Round => "The snapshot's last_updated, rounded to the nearest whole minute.",
+                        TimeRounding::Ceil => "The snapshot's last_updated, rounded up to a whole minute.",
+                    }),
+                ),
+                "source_file" => (None, Some("The snapshot file the row was read from.")),
+                "num_bikes_usable" => (
+                    Some("bikes"),
+                    Some("The feed's num_bikes_available less num_bikes_disabled, clamped at 0."),
+                ),
+                "last_reported" => (
+                    Some("milliseconds since the Unix epoch, UTC"),
+                    Some("When the station last reported, sent in seconds or milliseconds; null when the feed sends 0 or nothing."),
+                ),
+                "eightd_has_available_keys" => (
+                    None,
+                    Some("Whether the key-dispensing station has keys available; null when the feed leaves it out."),
+                ),
+                "occupancy_rolling_mean" => (
+                    Some("fraction"),
+                    Some("The station's mean of num_bikes_available / (num_bikes_available + num_docks_available) over its rows in the --rolling-window up to this one; null where none has both counts, or under --rolling-on-gap null while the window reaches into a gap."),
+                ),
+                _ => (None, None),
+            };
+            let (unit, description) = match original.strip_prefix("avail_") {
+                Some(type_id) if description.is_none() => (
+                    Some("vehicles"),
+                    Some(format!("Vehicles of type {type_id} available to rent, from vehicle_types_available; 0 where the station doesn't list the type.")),
+                ),
+                _ => (unit, description.map(String::from)),
+            };
+            ColumnDescription {
+                name: field.name().clone(),
+                data_type: field.data_type().to_string(),
+                nullable: field.is_nullable(),
+                unit,
+                description,
+            }
+        })
+        .collect()
+}
+
+/// Column builders for the rows of one record batch.
+pub(crate) struct Builders {
+    // Warning: You can specify Second here, and it won't work!
+    // https://github.com/apache/arrow-rs/issues/1920#issuecomment-1164220176
+    times: PrimitiveBuilder<TimestampMillisecondType>,
+    station_ids: PrimitiveBuilder<UInt16Type>,
+    num_bikes_available: PrimitiveBuilder<UInt16Type>,
+    num_ebikes_available: PrimitiveBuilder<UInt16Type>,
+    num_bikes_disabled: PrimitiveBuilder<UInt16Type>,
+    num_ebikes_disabled: PrimitiveBuilder<UInt16Type>,
+    num_classic_disabled: PrimitiveBuilder<UInt16Type>,
+    num_docks_available: PrimitiveBuilder<UInt16Type>,
+    is_returning: BooleanBuilder,
+    source_file: Option<StringDictionaryBuilder<Int32Type>>,
+    num_bikes_usable: Option<PrimitiveBuilder<UInt16Type>>,
+    last_reported: Option<PrimitiveBuilder<TimestampMillisecondType>>,
+    eightd_has_available_keys: Option<BooleanBuilder>,
+    occupancy_rolling_mean: Option<PrimitiveBuilder<Float64Type>>,
+    columns: Columns,
+    rows: usize,
+    bytes_per_row: usize,
+    /// Rows each batch's builders are allocated for.
+    capacity: usize,
+}
+
+impl Builders {
+    /// Builders with room for `capacity` rows in every batch, without
+    /// reallocating.
+    pub fn with_capacity(columns: &Columns, capacity: usize) -> Self {
+        Builders {
+            times: PrimitiveBuilder::with_capacity(capacity),
+            station_ids: PrimitiveBuilder::with_capacity(capacity),
+            num_bikes_available: PrimitiveBuilder::with_capacity(capacity),
+            num_ebikes_available: PrimitiveBuilder::with_capacity(capacity),
+            num_bikes_disabled: PrimitiveBuilder::with_capacity(capacity),
+            num_ebikes_disabled: PrimitiveBuilder::with_capacity(capacity),
+            num_classic_disabled: PrimitiveBuilder::with_capacity(capacity),
+            num_docks_available: PrimitiveBuilder::with_capacity(capacity),
+            is_returning: BooleanBuilder::with_capacity(capacity),
+            source_file: columns.source_file.then(StringDictionaryBuilder::new),
+            num_bikes_usable: columns
+                .usable_bikes
+                .then(|| PrimitiveBuilder::with_capacity(capacity)),
+            last_reported: columns
+                .last_reported
+                .then(|| PrimitiveBuilder::with_capacity(capacity)),
+            eightd_has_available_keys: columns
+                .eightd_has_available_keys
+                .then(|| BooleanBuilder::with_capacity(capacity)),
+            occupancy_rolling_mean: columns
+                .rolling_occupancy
+                .then(|| PrimitiveBuilder::with_capacity(capacity)),
+            columns: *columns,
+            rows: 0,
+            capacity,
+            bytes_per_row: schema(columns)
+                .fields()
+                .iter()
+                .map(|field| estimated_width(field.data_type()))
+                .sum(),
+        }
+    }
+
+    /// Rows appended since the last `finish`.
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    /// A rough estimate of what the buffered rows take up in memory.
+    pub fn estimated_bytes(&self) -> usize {
+        self.rows * self.bytes_per_row
+    }
+
+    /// Like `estimated_bytes`, but counting the rows allocated for up front
+    /// too.
+    pub fn allocated_bytes(&self) -> usize {
+        self.rows.max(self.capacity) * self.bytes_per_row
+    }
+
+    pub fn append(&mut self, row: &Row) {
+        self.rows += 1;
+        self.times.append_value(row.time);
+        self.station_ids.append_value(row.station_id);
+        self.num_bikes_available
+            .append_option(self.columns.bikes(row));
+        self.num_ebikes_available
+            .append_option(row.num_ebikes_available);
+        self.num_bikes_disabled
+            .append_option(row.num_bikes_disabled);
+        self.num_ebikes_disabled
+            .append_option(row.num_ebikes_disabled);
+        self.num_classic_disabled
+            .append_option(row.num_classic_disabled);
+        self.num_docks_available
+            .append_option(row.num_docks_available);
+        self.is_returning.append_option(row.is_returning);
+        if let Some(source_file) = &mut self.source_file {
+            source_file.append_option(row.source_file.as_deref());
+        }
+        if let Some(num_bikes_usable) = &mut self.num_bikes_usable {
+            num_bikes_usable.append_option(
+                row.num_bikes_available
+                    .zip(row.num_bikes_disabled)
+                    .map(|(available, disabled)| available.saturating_sub(disabled)),
+            );
+        }
+        if let Some(last_reported) = &mut self.last_reported {
+            last_reported.append_option(row.last_reported);
+        }
+        if let Some(eightd_has_available_keys) = &mut self.eightd_has_available_keys {
+            eightd_has_available_keys.append_option(row.eightd_has_available_keys);
+        }
+        if let Some(occupancy_rolling_mean) = &mut self.occupancy_rolling_mean {
+            occupancy_rolling_mean.append_option(row.occupancy_rolling_mean);
+        }
+    }
+
+    /// `station_names` holds the provider id for every mapped id, in id
+    /// order starting from 1, and is passed for a dictionary `station_ids`.
+    pub fn finish(&mut self, station_names: Option<StringArray>) -> RecordBatch {
+        // Swapped for fresh builders, since finishing one drops its capacity.
+        let mut full = mem::replace(self, Builders::with_capacity(&self.columns, self.capacity));
+        let station_ids = full.station_ids.finish();
+        let station_ids = match station_names {
+            Some(names) => {
+                let keys: Int32Array = station_ids.unary(|id| i32::from(id) - 1);
+                Arc::new(DictionaryArray::new(keys, Arc::new(names))) as ArrayRef
+            }
+            None => Arc::new(station_ids) as ArrayRef,
+        };
+        let mut columns = vec![
+            ("station_ids", station_ids),
+            (
+                full.columns.bikes_available(),
+                Arc::new(full.num_bikes_available.finish()) as ArrayRef,
+            ),
+            (
+                "num_ebikes_available",
+                Arc::new(full.num_ebikes_available.finish()) as ArrayRef,
+            ),
+            (
+                "num_bikes_disabled",
+                Arc::new(full.num_bikes_disabled.finish()) as ArrayRef,
+            ),
+            (
+                "num_ebikes_disabled",
+                Arc::new(full.num_ebikes_disabled.finish()) as ArrayRef,
+            ),
+            (
+                "num_classic_disabled",
+                Arc::new(full.num_classic_disabled.finish()) as ArrayRef,
+            ),
+            (
+                "num_docks_available",
+                Arc::new(full.num_docks_available.finish()) as ArrayRef,
+            ),
+            (
+                "is_returning",
+                Arc::new(full.is_returning.finish()) as ArrayRef,
+            ),
+            ("times", Arc::new(full.times.finish()) as ArrayRef),
+        ];
+        if let Some(source_file) = &mut full.source_file {
+            columns.push(("source_file", Arc::new(source_file.finish()) as ArrayRef));
+        }
+        if let Some(num_bikes_usable) = &mut full.num_bikes_usable {
+            columns.push((
+                "num_bikes_usable",
+                Arc::new(num_bikes_usable.finish()) as ArrayRef,
+            ));
+        }
+        if let Some(last_reported) = &mut full.last_reported {
+            columns.push((
+                "last_reported",
+                Arc::new(last_reported.finish()) as ArrayRef,
+            ));
+        }
+        if let Some(eightd_has_available_keys) = &mut full.eightd_has_available_keys {
+            columns.push((
+                "eightd_has_available_keys",
+                Arc::new(eightd_has_available_keys.finish()) as ArrayRef,
+            ));
+        }
+        if let Some(occupancy_rolling_mean) = &mut full.occupancy_rolling_mean {
+            columns.push((
+                "occupancy_rolling_mean",
+                Arc::new(occupancy_rolling_mean.finish()) as ArrayRef,
+            ));
+        }
+        RecordBatch::try_from_iter(columns).unwrap()
+    }
+}
+
+/// Applies `--rename` to the output's fields, failing on a name that isn't
+/// one of them, a column renamed twice, or two columns left with one name.
+/// Every rename is of a column's original name, so `a=b,b=c` renames both
+/// columns whichever order it's given in.
+pub(crate) fn rename(fields: &mut [FieldRef], renames: &[(String, String)]) -> Result<()> {
+    for (from, to) in renames {
+        if renames.iter().filter(|(other, _)| other == from).count() > 1 {
+            return Err(format!("--rename renames {from} more than once").into());
+        }
+        if !fields.iter().any(|field| field.name() == from) {
+            return Err(format!("--rename {from}={to}: the output has no {from} column").into());
+        }
+    }
+    for field in fields.iter_mut() {
+        if let Some((_, to)) = renames.iter().find(|(from, _)| from == field.name()) {
+            *field = Arc::new(field.as_ref().clone().with_name(to));
+        }
+    }
+    let mut names = HashSet::new();
+    for field in fields.iter() {
+        if !names.insert(field.name()) {
+            return Err(format!("--rename leaves two columns named {}", field.name()).into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the builders produce the columns of `schema(columns)`, by
+/// name and type in order, so a mismatch between the two lists fails before
+/// any input is read rather than at the first write.
+pub(crate) fn check_schema(columns: &Columns) -> Result<()> {
+    let station_names = columns
+        .station_id_dictionary
+        .then(|| StringArray::from(Vec::<&str>::new()));
+    let built = Builders::with_capacity(columns, 0).finish(station_names);
+    same_fields(schema(columns).fields(), built.schema().fields())
+}
+
+/// Fails unless both lists have the same names and types in the same order.
+fn same_fields(expected: &Fields, actual: &Fields) -> Result<()> {
+    let describe = |fields: &Fields| {
+        fields
+            .iter()
+            .map(|field| format!("{}: {}", field.name(), field.data_type()))
+            .collect::<Vec<_>>()
+    };
+    let (expected, actual) = (describe(expected), describe(actual));
+    if actual != expected {
+        return Err(format!(
+            "internal error: the column builders produce {actual:?} but the schema is {expected:?}"
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Bytes a value of this type takes in a builder, counting a byte for the
+/// validity bitmap and dictionary keys for dictionary columns.
+fn estimated_width(data_type: &DataType) -> usize {
+    match data_type {
+        DataType::Dictionary(key, _) => estimated_width(key),
+        data_type => data_type.primitive_width().unwrap_or(0) + 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builders_match_the_schema_for_every_flag() {
+        for flag in [false, true] {
+            let columns = Columns {
+                source_file: flag,
+                usable_bikes: flag,
+                classic_bikes: flag,
+                station_id_dictionary: flag,
+                last_reported: flag,
+                eightd_has_available_keys: flag,
+                rolling_occupancy: flag,
+                ..Columns::default()
+            };
+            check_schema(&columns).unwrap();
+        }
+    }
+
+    #[test]
+    fn same_fields_trips_on_a_name_type_or_order_difference() {
+        let fields = |fields: &[(&str, DataType)]| -> Fields {
+            fields
+                .iter()
+                .map(|(name, data_type)| Field::new(*name, data_type.clone(), true))
+                .collect()
+        };
+        let expected = fields(&[("times", DataType::Int64), ("bikes", DataType::UInt16)]);
+        same_fields(&expected, &expected.clone()).unwrap();
+        for actual in [
+            fields(&[("times", DataType::Int64), ("docks", DataType::UInt16)]),
+            fields(&[("times", DataType::Int64), ("bikes", DataType::UInt32)]),
+            fields(&[("bikes", DataType::UInt16), ("times", DataType::Int64)]),
+            fields(&[("times", DataType::Int64)]),
+        ] {
+            let err = same_fields(&expected, &actual).unwrap_err().to_string();
+            assert!(err.contains("the column builders produce"), "{err}");
+        }
+    }
+
+    #[test]
+    fn rename_rejects_two_columns_with_one_name() {
+        let renames = |pairs: &[(&str, &str)]| -> Result<Vec<String>> {
+            let mut fields = schema(&Columns::default()).fields().to_vec();
+            let pairs: Vec<(String, String)> = pairs
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect();
+            rename(&mut fields, &pairs)?;
+            Ok(fields.iter().map(|field| field.name().clone()).collect())
+        };
+        let names = renames(&[("times", "ts"), ("station_ids", "station")]).unwrap();
+        assert_eq!(names[0], "station");
+        assert!(names.contains(&"ts".to_string()), "{names:?}");
+        // Chained renames are of the original names, in either order.
+        let times = names.iter().position(|name| name == "ts").unwrap();
+        for pairs in [
+            &[("station_ids", "times"), ("times", "ts")][..],
+            &[("times", "ts"), ("station_ids", "times")][..],
+        ] {
+            let names = renames(pairs).unwrap();
+            assert_eq!((&*names[0], &*names[times]), ("times", "ts"));
+        }
+        let names = renames(&[("times", "station_ids"), ("station_ids", "times")]).unwrap();
+        assert_eq!((&*names[0], &*names[times]), ("times", "station_ids"));
+
+        for (pairs, message) in [
+            (
+                &[("times", "ts"), ("station_ids", "ts")][..],
+                "--rename leaves two columns named ts",
+            ),
+            (
+                &[("times", "station_ids")][..],
+                "--rename leaves two columns named station_ids",
+            ),
+            (
+                &[("times", "ts"), ("times", "t")][..],
+                "--rename renames times more than once",
+            ),
+            (
+                &[("nope", "ts")][..],
+                "--rename nope=ts: the output has no nope column",
+            ),
+        ] {
+            let err = renames(pairs).unwrap_err().to_string();
+            assert_eq!(err, message);
+        }
+    }
+}
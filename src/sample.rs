@@ -0,0 +1,110 @@
+use crate::row::{Columns, Row};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// A uniform random sample of at most `capacity` rows out of everything
+/// offered to it (Algorithm R), kept for QA spot checks.
+pub struct Reservoir {
+    capacity: usize,
+    seen: usize,
+    rows: Vec<Row>,
+    rng: StdRng,
+}
+
+impl Reservoir {
+    /// Without a `seed`, the sample differs from run to run.
+    pub fn new(capacity: usize, seed: Option<u64>) -> Self {
+        Reservoir {
+            capacity,
+            seen: 0,
+            rows: Vec::with_capacity(capacity),
+            rng: seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+        }
+    }
+
+    pub fn offer(&mut self, row: &Row) {
+        self.seen += 1;
+        if self.rows.len() < self.capacity {
+            self.rows.push(row.clone());
+        } else {
+            let slot = self.rng.gen_range(0..self.seen);
+            if slot < self.capacity {
+                self.rows[slot] = row.clone();
+            }
+        }
+    }
+
+    /// Writes the sample as JSON lines, the bikes column named and valued
+    /// as in the parquet output's `columns`. With `station_names`, indexed
+    /// by mapped id less one, rows carry the provider's station ids instead.
+    pub fn write(
+        &self,
+        path: &Path,
+        columns: &Columns,
+        station_names: Option<&[&str]>,
+    ) -> std::io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        for row in &self.rows {
+            let mut value = serde_json::to_value(row)?;
+            if let Some(fields) = value.as_object_mut() {
+                fields.remove("num_bikes_available");
+                fields.insert(columns.bikes_available().into(), columns.bikes(row).into());
+            }
+            if let Some(names) = station_names {
+                value["station_ids"] = names[usize::from(row.station_id) - 1].into();
+            }
+            serde_json::to_writer(&mut out, &value)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(station_id: u16) -> Row {
+        Row {
+            station_id,
+            num_bikes_available: Some(1),
+            num_ebikes_available: Some(0),
+            num_bikes_disabled: Some(0),
+            num_ebikes_disabled: None,
+            num_classic_disabled: Some(0),
+            num_docks_available: Some(1),
+            is_returning: Some(true),
+            time: 0,
+            source_file: None,
+            last_reported: None,
+            eightd_has_available_keys: None,
+            occupancy_rolling_mean: None,
+        }
+    }
+
+    fn sample(capacity: usize, offered: u16) -> Vec<u16> {
+        let mut reservoir = Reservoir::new(capacity, Some(7));
+        for station_id in 1..=offered {
+            reservoir.offer(&row(station_id));
+        }
+        reservoir.rows.iter().map(|row| row.station_id).collect()
+    }
+
+    #[test]
+    fn keeps_capacity_rows_from_the_input() {
+        let mut kept = sample(10, 1000);
+        assert_eq!(kept.len(), 10);
+        assert!(kept.iter().all(|id| (1..=1000).contains(id)));
+        kept.sort_unstable();
+        kept.dedup();
+        assert_eq!(kept.len(), 10);
+    }
+
+    #[test]
+    fn keeps_every_row_when_offered_fewer() {
+        assert_eq!(sample(10, 4), [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,78 @@
+use serde::Serialize;
+use std::fs::File;
+use std::path::Path;
+
+/// How far each snapshot's modification time is from the feed's
+/// `last_updated`. Large gaps point at a lagging feed or a downloader with
+/// a wrong clock.
+pub struct SkewReport {
+    threshold: i64,
+    samples: Vec<Sample>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Sample {
+    file: String,
+    modified: i64,
+    last_updated: i64,
+    skew_seconds: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    threshold_seconds: i64,
+    files: usize,
+    min: Option<i64>,
+    p50: Option<i64>,
+    p90: Option<i64>,
+    max: Option<i64>,
+    mean: Option<f64>,
+    /// Files whose skew is beyond the threshold either way.
+    flagged: Vec<&'a Sample>,
+}
+
+impl SkewReport {
+    pub fn new(threshold: i64) -> Self {
+        SkewReport {
+            threshold,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Both times are in seconds since the epoch.
+    pub fn add(&mut self, file: &str, modified: i64, last_updated: i64) {
+        self.samples.push(Sample {
+            file: file.to_string(),
+            modified,
+            last_updated,
+            skew_seconds: modified - last_updated,
+        });
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut skews: Vec<i64> = self.samples.iter().map(|s| s.skew_seconds).collect();
+        skews.sort_unstable();
+        // Nearest rank, so every reported value is one that was observed.
+        let percentile = |p: usize| {
+            let rank = (p * skews.len()).div_ceil(100).max(1);
+            skews.get(rank - 1).copied()
+        };
+        let report = Report {
+            threshold_seconds: self.threshold,
+            files: skews.len(),
+            min: skews.first().copied(),
+            p50: percentile(50),
+            p90: percentile(90),
+            max: skews.last().copied(),
+            mean: (!skews.is_empty())
+                .then(|| skews.iter().sum::<i64>() as f64 / skews.len() as f64),
+            flagged: self
+                .samples
+                .iter()
+                .filter(|s| s.skew_seconds.abs() > self.threshold)
+                .collect(),
+        };
+        serde_json::to_writer_pretty(File::create(path)?, &report)?;
+        Ok(())
+    }
+}
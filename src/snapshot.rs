@@ -0,0 +1,994 @@
+use flate2::bufread;
+use glob::glob;
+use jsonschema::JSONSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+use crate::args::{Args, OnError};
+use crate::feeds::FEED_OUTPUTS;
+use crate::warnings::{WarningKind, Warnings};
+use crate::Result;
+
+pub(crate) const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Where GBFS puts the stations array, which `StationStatus` reads directly.
+pub(crate) const DEFAULT_STATIONS_KEY: &str = "data.stations";
+
+/// The default `--ebike-vehicle-types`.
+pub(crate) const DEFAULT_EBIKE_VEHICLE_TYPES: &str = "2";
+
+/// GBFS feeds besides station_status that a download directory might hold
+/// alongside it.
+const OTHER_FEEDS: &[&str] = &[
+    "station_information",
+    "free_bike_status",
+    "vehicle_status",
+    "vehicle_types",
+    "system_information",
+    "system_regions",
+    "system_pricing_plans",
+    "system_alerts",
+    "geofencing_zones",
+];
+
+const STATION_STATUS: &str = "station_status";
+
+/// The feed `path`'s file name names, station_status included, if any.
+/// Files naming no feed are read as station_status unless their content
+/// says otherwise.
+fn named_feed(path: &str) -> Option<&'static str> {
+    let file_name = Path::new(path).file_name()?.to_str()?;
+    if file_name.contains(STATION_STATUS) {
+        return Some(STATION_STATUS);
+    }
+    OTHER_FEEDS
+        .iter()
+        .copied()
+        .find(|feed| file_name.contains(feed))
+}
+
+/// A snapshot of another feed: one `--other-feeds-dir` writes out, or one
+/// whose name didn't say and whose content doesn't parse as station_status.
+#[derive(Debug)]
+pub(crate) struct OtherFeed {
+    pub feed: &'static str,
+    /// The decompressed document.
+    pub json: Vec<u8>,
+}
+
+impl std::fmt::Display for OtherFeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "looks like a {} snapshot, not station_status", self.feed)
+    }
+}
+
+impl std::error::Error for OtherFeed {}
+
+/// The feed a document that failed to parse as station_status is, going by
+/// what `data` holds: free_bike_status lists `bikes`, vehicle_status
+/// `vehicles`, and station_information has `stations` without counts.
+fn sniff_feed(json: &[u8]) -> Option<&'static str> {
+    let document: serde_json::Value = serde_json::from_slice(json).ok()?;
+    let data = document.get("data")?;
+    if data.get("bikes").is_some() {
+        return Some("free_bike_status");
+    }
+    if data.get("vehicles").is_some() {
+        return Some("vehicle_status");
+    }
+    let first = data.get("stations")?.get(0)?;
+    first
+        .get("num_bikes_available")
+        .is_none()
+        .then_some("station_information")
+}
+
+/// One input, as `for_each_feed` hands it on.
+pub(crate) enum Input<T> {
+    /// A station_status snapshot, or the error `--on-error` skipped it for.
+    StationStatus(Result<T>),
+    /// A snapshot of one of the feeds `--other-feeds-dir` writes out.
+    Other(OtherFeed),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VehicleTypeAvailable {
+    pub vehicle_type_id: String,
+    /// Vehicles of this type available to rent.
+    #[serde(default)]
+    pub count: Option<i64>,
+    #[serde(default)]
+    pub num_bikes_disabled: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct Station {
+    #[serde(skip)]
+    pub legacy_id: String,
+    /// Seconds or milliseconds since the epoch; see `epoch_millis`.
+    #[serde(default)]
+    pub last_reported: Option<i64>,
+    pub num_ebikes_available: i64,
+    pub num_bikes_available: i64,
+    pub is_returning: u32,
+    /// Whether a key-dispensing station has keys to hand out; most feeds
+    /// leave it out.
+    #[serde(default)]
+    pub eightd_has_available_keys: Option<bool>,
+    pub num_docks_available: i64,
+    pub num_docks_disabled: i64,
+    pub is_installed: u32,
+    pub num_bikes_disabled: i64,
+    pub station_id: String,
+    pub station_status: String,
+    pub is_renting: u32,
+    #[serde(default)]
+    pub vehicle_types_available: Vec<VehicleTypeAvailable>,
+}
+
+impl Station {
+    /// Applies `--strip-id-prefix`, returning the original id if it changed.
+    pub fn strip_id_prefix(&mut self, prefix: Option<&str>) -> Option<String> {
+        let stripped = self.station_id.strip_prefix(prefix?)?.to_string();
+        Some(std::mem::replace(&mut self.station_id, stripped))
+    }
+
+    /// `is_returning` is a 0/1 integer in the feed. Anything else is
+    /// reported and stored as null.
+    pub fn is_returning(&self, warnings: &mut Warnings) -> Option<bool> {
+        match self.is_returning {
+            0 => Some(false),
+            1 => Some(true),
+            other => {
+                warnings.report(
+                    Some(&self.station_id),
+                    WarningKind::InvalidIsReturning,
+                    format!("is_returning = {other}"),
+                );
+                None
+            }
+        }
+    }
+
+    /// A count from the feed, or null if it is negative or above `max`.
+    /// Some feeds send -1 for unknown, or an unsigned wraparound like 65535.
+    pub fn count(&self, field: &str, value: i64, max: u16, warnings: &mut Warnings) -> Option<u16> {
+        match u16::try_from(value) {
+            Ok(count) if count <= max => Some(count),
+            _ => {
+                warnings.report(
+                    Some(&self.station_id),
+                    WarningKind::CountOutOfRange,
+                    format!("{field} = {value}, above {max} or negative"),
+                );
+                None
+            }
+        }
+    }
+
+    /// Splits disabled bikes into (e-bike, classic) counts, the e-bikes
+    /// being the types in `ebike_types`. Without a
+    /// per-type breakdown the e-bike count is unknown and the combined
+    /// `num_bikes_disabled` is reported as classic. A sentinel anywhere in
+    /// the breakdown, or a side adding up to more than a u16 holds, makes
+    /// that side of it null.
+    pub fn disabled_breakdown(
+        &self,
+        ebike_types: &[String],
+        max_bikes: u16,
+        warnings: &mut Warnings,
+    ) -> (Option<u16>, Option<u16>) {
+        let mut ebikes = None;
+        let mut classic = None;
+        for vehicle_type in &self.vehicle_types_available {
+            let Some(disabled) = vehicle_type.num_bikes_disabled else {
+                continue;
+            };
+            let disabled = self.count(
+                "vehicle_types_available.num_bikes_disabled",
+                disabled,
+                max_bikes,
+                warnings,
+            );
+            let total = if ebike_types.contains(&vehicle_type.vehicle_type_id) {
+                &mut ebikes
+            } else {
+                &mut classic
+            };
+            let sum = match (total.unwrap_or(Some(0u16)), disabled) {
+                (Some(sum), Some(disabled)) => {
+                    let sum = sum.checked_add(disabled);
+                    if sum.is_none() {
+                        warnings.report(
+                            Some(&self.station_id),
+                            WarningKind::CountOutOfRange,
+                            format!(
+                                "vehicle_types_available.num_bikes_disabled adds up to more than {}",
+                                u16::MAX
+                            ),
+                        );
+                    }
+                    sum
+                }
+                _ => None,
+            };
+            *total = Some(sum);
+        }
+        match (ebikes, classic) {
+            (None, None) => (
+                None,
+                self.count(
+                    "num_bikes_disabled",
+                    self.num_bikes_disabled,
+                    max_bikes,
+                    warnings,
+                ),
+            ),
+            (ebikes, classic) => (ebikes.unwrap_or(Some(0)), classic.unwrap_or(Some(0))),
+        }
+    }
+}
+
+/// Timestamps at least this large are taken to be in milliseconds: as
+/// seconds they'd be past the year 5000, as milliseconds they're after 1973.
+const MILLIS_FROM: i64 = 100_000_000_000;
+
+/// A feed timestamp, which providers send in seconds or milliseconds, as
+/// milliseconds since the epoch. Null for 0 or negative, the sentinels some
+/// feeds send for unknown.
+pub fn epoch_millis(value: i64) -> Option<i64> {
+    match value {
+        ..=0 => None,
+        MILLIS_FROM.. => Some(value),
+        seconds => Some(seconds * 1000),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Data {
+    pub stations: Vec<Station>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct StationStatus {
+    pub data: Data,
+    pub last_updated: i64,
+    #[serde(skip)]
+    pub ttl: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileTiming {
+    pub file: String,
+    pub decompress_ms: f64,
+    pub parse_ms: f64,
+    pub stations: usize,
+}
+
+impl FileTiming {
+    pub fn total_ms(&self) -> f64 {
+        self.decompress_ms + self.parse_ms
+    }
+}
+
+/// Parses with simd-json, returning `None` so the caller can fall back to
+/// serde_json. simd-json parses in place, so it works on its own copy.
+#[cfg(feature = "simd")]
+fn parse_fast(json: &[u8]) -> Option<StationStatus> {
+    let mut scratch = json.to_vec();
+    match simd_json::serde::from_slice(&mut scratch) {
+        Ok(status) => Some(status),
+        Err(err) => {
+            debug!(error = %err, "simd-json failed, falling back to serde_json");
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "simd"))]
+fn parse_fast(_json: &[u8]) -> Option<StationStatus> {
+    None
+}
+
+/// Parses a document whose stations are at `key`, a dotted path like
+/// `data.bikes`, going through a `serde_json::Value` to find them.
+fn parse_keyed(json: &[u8], key: &str) -> Result<StationStatus> {
+    let mut document: serde_json::Value = serde_json::from_slice(json)?;
+    let last_updated = document
+        .get("last_updated")
+        .and_then(serde_json::Value::as_i64)
+        .ok_or("no last_updated")?;
+    let stations = key
+        .split('.')
+        .try_fold(&mut document, |value, part| value.get_mut(part))
+        .ok_or_else(|| format!("no {key}"))?
+        .take();
+    Ok(StationStatus {
+        data: Data {
+            stations: serde_json::from_value(stations).map_err(|err| format!("{key}: {err}"))?,
+        },
+        last_updated,
+        ttl: 0,
+    })
+}
+
+/// Passes reads through while hashing every byte that goes by.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Option<Sha256>,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+pub struct Snapshot {
+    pub status: StationStatus,
+    pub timing: FileTiming,
+    /// Hex SHA-256 of the file as stored on disk, with `--checksum`.
+    pub checksum: Option<String>,
+    /// When the file (or tar member) was last modified, in seconds since the
+    /// epoch, if known.
+    pub modified: Option<i64>,
+}
+
+/// Hands the rest of `input` to `f` a buffer at a time. Unlike
+/// `read_to_end` and `io::copy`, which read straight into bigger buffers of
+/// their own, this keeps every read at `--read-buffer-size`.
+fn drain(input: &mut impl BufRead, mut f: impl FnMut(&[u8])) -> std::io::Result<()> {
+    loop {
+        let bytes = input.fill_buf()?;
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        f(bytes);
+        let read = bytes.len();
+        input.consume(read);
+    }
+}
+
+/// Decodes one snapshot, gzipped or plain JSON depending on its magic bytes.
+/// `feed` is the feed its name names; any but station_status is returned
+/// decompressed as an `OtherFeed` error, as is one whose name says nothing
+/// and whose content turns out to be another feed's.
+fn read_snapshot(
+    name: &str,
+    feed: Option<&'static str>,
+    reader: impl Read,
+    args: &Args,
+    gbfs_schema: Option<&JSONSchema>,
+) -> Result<Snapshot> {
+    let started = Instant::now();
+    let mut input = BufReader::with_capacity(
+        args.read_buffer_size,
+        HashingReader {
+            inner: reader,
+            hasher: args.checksum.then(Sha256::new),
+        },
+    );
+    let gzipped = input.fill_buf()?.starts_with(&GZIP_MAGIC);
+    let mut json = Vec::with_capacity(args.decompress_buffer_size.unwrap_or(0));
+    if gzipped {
+        bufread::GzDecoder::new(&mut input).read_to_end(&mut json)?;
+    } else {
+        drain(&mut input, |bytes| json.extend_from_slice(bytes))?;
+    }
+    // Anything after the gzip member still counts towards the file's hash.
+    drain(&mut input, |_| ())?;
+    let checksum = input
+        .into_inner()
+        .hasher
+        .map(|hasher| format!("{:x}", hasher.finalize()));
+    let decompressed = Instant::now();
+    if let Some(feed) = feed.filter(|feed| *feed != STATION_STATUS) {
+        return Err(OtherFeed { feed, json }.into());
+    }
+    if let Some(gbfs_schema) = gbfs_schema {
+        validate(name, &json, gbfs_schema)?;
+    }
+    let fast = if args.fast_json && args.stations_key == DEFAULT_STATIONS_KEY {
+        parse_fast(&json)
+    } else {
+        None
+    };
+    let mut status: StationStatus = match fast {
+        Some(status) => status,
+        None if args.stations_key == DEFAULT_STATIONS_KEY => match serde_json::from_slice(&json) {
+            Ok(status) => status,
+            // A file named station_status is never taken for another feed.
+            Err(err) if feed.is_none() => match sniff_feed(&json) {
+                Some(feed) => return Err(OtherFeed { feed, json }.into()),
+                None => return Err(err.into()),
+            },
+            Err(err) => return Err(err.into()),
+        },
+        None => parse_keyed(&json, &args.stations_key)?,
+    };
+    // Normalized to seconds, like `last_reported` is to milliseconds.
+    status.last_updated = epoch_millis(status.last_updated)
+        .ok_or_else(|| format!("last_updated is {}", status.last_updated))?
+        .div_euclid(1000);
+    let timing = FileTiming {
+        file: name.to_string(),
+        decompress_ms: (decompressed - started).as_secs_f64() * 1000.0,
+        parse_ms: decompressed.elapsed().as_secs_f64() * 1000.0,
+        stations: status.data.stations.len(),
+    };
+    debug!(
+        file = %timing.file,
+        decompress_ms = timing.decompress_ms,
+        parse_ms = timing.parse_ms,
+        stations = timing.stations,
+        "timing"
+    );
+    Ok(Snapshot {
+        status,
+        timing,
+        checksum,
+        modified: None,
+    })
+}
+
+/// Checks a document against `--gbfs-schema`, logging every violation.
+fn validate(name: &str, json: &[u8], gbfs_schema: &JSONSchema) -> Result<()> {
+    let document: serde_json::Value = serde_json::from_slice(json)?;
+    let Err(errors) = gbfs_schema.validate(&document) else {
+        return Ok(());
+    };
+    let mut violations = 0;
+    for error in errors {
+        warn!(file = name, path = %error.instance_path, error = %error, "schema violation");
+        violations += 1;
+    }
+    Err(format!("{violations} violation(s) of --gbfs-schema").into())
+}
+
+/// Compiles the `--gbfs-schema` document.
+fn compile_gbfs_schema(path: &Path) -> Result<JSONSchema> {
+    let schema: serde_json::Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    JSONSchema::compile(&schema)
+        .map_err(|err| format!("invalid schema {}: {err}", path.display()).into())
+}
+
+/// Applies `--on-error` to an input that failed to read: under `skip` the
+/// error is handed on for the caller to report, under `fail` it propagates.
+fn skip_unreadable<T>(name: &str, result: Result<T>, on_error: OnError) -> Result<Result<T>> {
+    match result {
+        Err(err) if on_error == OnError::Fail => Err(format!("{name}: {err}").into()),
+        result => Ok(result),
+    }
+}
+
+/// How many of the sorted `paths` come up to and including `resume`.
+fn resume_position(paths: &[PathBuf], resume: &Path) -> Result<usize> {
+    let canonical = resume.canonicalize().ok();
+    paths
+        .iter()
+        .position(|path| {
+            path == resume || canonical.is_some() && path.canonicalize().ok() == canonical
+        })
+        .map(|index| index + 1)
+        .ok_or_else(|| {
+            format!(
+                "--resume-from-file {} isn't matched by --input",
+                resume.display()
+            )
+            .into()
+        })
+}
+
+/// Feeds every input snapshot to `f` in order, from the `--input` glob or
+/// the members of `--input-tar`. Unreadable snapshots that `--on-error`
+/// skips are passed as the error that made them unreadable.
+pub fn for_each_snapshot(
+    args: &Args,
+    mut f: impl FnMut(&str, Result<Snapshot>) -> Result<()>,
+) -> Result<()> {
+    for_each_snapshot_input(args, false, |name, input| match input {
+        Input::StationStatus(snapshot) => f(name, snapshot),
+        Input::Other(_) => Ok(()),
+    })
+}
+
+/// Like `for_each_snapshot`, also handing on the snapshots of the feeds
+/// `--other-feeds-dir` writes out, when it's given.
+pub(crate) fn for_each_feed(
+    args: &Args,
+    f: impl FnMut(&str, Input<Snapshot>) -> Result<()>,
+) -> Result<()> {
+    for_each_snapshot_input(args, args.other_feeds_dir.is_some(), f)
+}
+
+fn for_each_snapshot_input(
+    args: &Args,
+    route: bool,
+    f: impl FnMut(&str, Input<Snapshot>) -> Result<()>,
+) -> Result<()> {
+    let gbfs_schema = match &args.gbfs_schema {
+        Some(path) => Some(compile_gbfs_schema(path)?),
+        None => None,
+    };
+    let gbfs_schema = gbfs_schema.as_ref();
+    for_each_input(
+        args,
+        route,
+        |name, feed, input, modified| {
+            let mut snapshot = read_snapshot(name, feed, input, args, gbfs_schema)?;
+            snapshot.modified = modified;
+            Ok(snapshot)
+        },
+        f,
+    )
+}
+
+/// Feeds every input's time to `f` in order, like `for_each_snapshot`, in
+/// milliseconds since the epoch, passing over the same files it would. For
+/// a file named station_status the time is taken from its name when it is
+/// a unix time, as the downloader names them, and otherwise from
+/// `last_updated` without keeping the stations. A file whose name doesn't
+/// say which feed it is gets read in full, to tell it from another feed's.
+pub fn for_each_time(
+    args: &Args,
+    mut f: impl FnMut(&str, Result<i64>) -> Result<()>,
+) -> Result<()> {
+    for_each_input(
+        args,
+        false,
+        |name, feed, input, _| match (feed, name_time(name)) {
+            (Some(_), Some(time)) => Ok(time),
+            (Some(_), None) => read_last_updated(input, args),
+            (None, time) => {
+                let snapshot = read_snapshot(name, None, input, args, None)?;
+                Ok(time.unwrap_or(snapshot.status.last_updated * 1000))
+            }
+        },
+        |name, input| match input {
+            Input::StationStatus(time) => f(name, time),
+            Input::Other(_) => Ok(()),
+        },
+    )
+}
+
+/// The unix time, in seconds or milliseconds, that a file like
+/// `1688000000.json.gz` is named by.
+fn name_time(name: &str) -> Option<i64> {
+    let file_name = Path::new(name).file_name()?.to_str()?;
+    let stem = file_name.split('.').next()?;
+    // Shorter numbers are too early to be a snapshot's time.
+    if stem.len() < 9 || !stem.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    epoch_millis(stem.parse().ok()?)
+}
+
+/// Reads just a snapshot's `last_updated`, in milliseconds since the epoch.
+fn read_last_updated(input: impl Read, args: &Args) -> Result<i64> {
+    #[derive(Deserialize)]
+    struct LastUpdated {
+        last_updated: i64,
+    }
+    let mut input = BufReader::with_capacity(args.read_buffer_size, input);
+    let LastUpdated { last_updated } = if input.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        serde_json::from_reader(bufread::GzDecoder::new(input))?
+    } else {
+        serde_json::from_reader(input)?
+    };
+    epoch_millis(last_updated).ok_or_else(|| format!("last_updated is {last_updated}").into())
+}
+
+/// The files `--input-list` lists, or else those the `--input` glob
+/// matches.
+pub(crate) fn input_paths(args: &Args) -> Result<Vec<PathBuf>> {
+    let Some(list) = &args.input_list else {
+        return Ok(glob(&args.input)?.collect::<Result<_, _>>()?);
+    };
+    let list = fs::read_to_string(list)
+        .map_err(|err| format!("reading --input-list {}: {err}", list.display()))?;
+    Ok(list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Hands every input to `read`, with the feed its name names and its
+/// modification time in seconds since the epoch if known, and what it
+/// returns to `f`, applying `--on-error` in between. Files named for
+/// another feed are skipped unread, unless `route` is set and they're of a
+/// feed `--other-feeds-dir` writes out; those, and unnamed files `read`
+/// finds to be one, are handed on as `Input::Other`. Any other unnamed
+/// file `read` finds to be another feed's is unreadable, since it might
+/// as well be a broken station_status snapshot.
+fn for_each_input<T>(
+    args: &Args,
+    route: bool,
+    mut read: impl FnMut(&str, Option<&'static str>, &mut dyn Read, Option<i64>) -> Result<T>,
+    mut f: impl FnMut(&str, Input<T>) -> Result<()>,
+) -> Result<()> {
+    let routes = |feed: &str| route && FEED_OUTPUTS.contains(&feed);
+    let mut other_feeds: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut skip_other_feed = |name: &str, feed: Option<&'static str>| match feed {
+        Some(feed) if feed != STATION_STATUS && !routes(feed) => {
+            debug!(file = name, feed, "skipping another feed's snapshot");
+            *other_feeds.entry(feed).or_default() += 1;
+            true
+        }
+        _ => false,
+    };
+    let mut hand_on = |name: &str, result: Result<T>| {
+        let result = match result.map_err(|err| err.downcast::<OtherFeed>()) {
+            Ok(read) => Ok(read),
+            Err(Ok(other)) if routes(other.feed) => return f(name, Input::Other(*other)),
+            Err(Ok(other)) => Err(other as Box<dyn std::error::Error>),
+            Err(Err(err)) => Err(err),
+        };
+        f(
+            name,
+            Input::StationStatus(skip_unreadable(name, result, args.on_error)?),
+        )
+    };
+    let Some(tar_path) = &args.input_tar else {
+        let mut paths = input_paths(args)?;
+        if let Some(resume) = &args.resume_from_file {
+            let skipped = resume_position(&paths, resume)?;
+            info!(file = %resume.display(), skipped, "resuming after");
+            paths.drain(..skipped);
+        }
+        for path in paths {
+            let name = path.display().to_string();
+            let feed = named_feed(&name);
+            if skip_other_feed(&name, feed) {
+                continue;
+            }
+            let result = File::open(&path).map_err(Into::into).and_then(|mut file| {
+                let modified = file
+                    .metadata()?
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|since| since.as_secs() as i64);
+                read(&name, feed, &mut file, modified)
+            });
+            hand_on(&name, result)?;
+        }
+        log_other_feeds(&other_feeds);
+        return Ok(());
+    };
+    let mut input = BufReader::with_capacity(args.read_buffer_size, File::open(tar_path)?);
+    let archive: Box<dyn Read> = if input.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Box::new(bufread::GzDecoder::new(input))
+    } else {
+        Box::new(input)
+    };
+    let mut archive = tar::Archive::new(archive);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let member = entry.path()?.display().to_string();
+        if !entry.header().entry_type().is_file()
+            || !(member.ends_with(".json") || member.ends_with(".json.gz"))
+        {
+            continue;
+        }
+        let name = format!("{}:{member}", tar_path.display());
+        let feed = named_feed(&member);
+        if skip_other_feed(&name, feed) {
+            continue;
+        }
+        let modified = entry.header().mtime().ok().map(|mtime| mtime as i64);
+        let result = read(&name, feed, &mut entry, modified);
+        hand_on(&name, result)?;
+    }
+    log_other_feeds(&other_feeds);
+    Ok(())
+}
+
+fn log_other_feeds(other_feeds: &BTreeMap<&str, usize>) {
+    for (feed, files) in other_feeds {
+        info!(feed, files, "skipped snapshots of another feed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_fixtures::{document, read_json, station, write_gz, TempDir, T0};
+
+    fn parse(station: serde_json::Value) -> Station {
+        serde_json::from_value(station).unwrap()
+    }
+
+    #[test]
+    fn is_returning_is_a_boolean_or_null() {
+        let mut warnings = Warnings::new(true);
+        for (value, expected) in [(0, Some(false)), (1, Some(true)), (7, None)] {
+            let mut fixture = station("A", 1, 0, 1);
+            fixture["is_returning"] = json!(value);
+            assert_eq!(parse(fixture).is_returning(&mut warnings), expected);
+        }
+        let dir = TempDir::new();
+        warnings.write(&dir.join("warnings.json")).unwrap();
+        let warnings = read_json(&dir.join("warnings.json"));
+        assert_eq!(warnings.as_array().unwrap().len(), 1);
+        assert_eq!(warnings[0]["kind"], "invalid_is_returning");
+        assert_eq!(warnings[0]["detail"], "is_returning = 7");
+    }
+
+    #[test]
+    fn a_disabled_breakdown_over_a_u16_is_null() {
+        let mut fixture = station("A", 1, 0, 1);
+        fixture["vehicle_types_available"] = json!([
+            { "vehicle_type_id": "1", "num_bikes_disabled": 40_000 },
+            { "vehicle_type_id": "2", "num_bikes_disabled": 3 },
+            { "vehicle_type_id": "3", "num_bikes_disabled": 40_000 },
+        ]);
+        let mut warnings = Warnings::new(false);
+        assert_eq!(
+            parse(fixture).disabled_breakdown(&["2".to_string()], u16::MAX, &mut warnings),
+            (Some(3), None)
+        );
+        assert_eq!(warnings.count(), 1);
+    }
+
+    #[test]
+    fn ebike_vehicle_types_pick_the_ebike_side_of_the_breakdown() {
+        let mut fixture = station("A", 1, 0, 1);
+        fixture["vehicle_types_available"] = json!([
+            { "vehicle_type_id": "2", "num_bikes_disabled": 1 },
+            { "vehicle_type_id": "e", "num_bikes_disabled": 2 },
+            { "vehicle_type_id": "pedal", "num_bikes_disabled": 4 },
+        ]);
+        let station = parse(fixture);
+        let mut warnings = Warnings::new(false);
+        let split = |argv: &[&str], warnings: &mut Warnings| {
+            let args = Args::parse_from(["bike-1"].iter().chain(argv));
+            station.disabled_breakdown(&args.ebike_vehicle_types, u16::MAX, warnings)
+        };
+        assert_eq!(split(&[], &mut warnings), (Some(1), Some(6)));
+        assert_eq!(
+            split(&["--ebike-vehicle-types", "e"], &mut warnings),
+            (Some(2), Some(5))
+        );
+        assert_eq!(
+            split(&["--ebike-vehicle-types", "2,e"], &mut warnings),
+            (Some(3), Some(4))
+        );
+    }
+
+    #[test]
+    fn reads_both_members_of_a_tar() {
+        let dir = TempDir::new();
+        let first = dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let plain = dir.join("plain.json");
+        fs::write(
+            &plain,
+            serde_json::to_vec(&document(
+                T0 + 60,
+                vec![station("A", 2, 0, 0), station("B", 1, 0, 1)],
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        let tar_path = dir.join("snapshots.tar");
+        let mut tar = tar::Builder::new(File::create(&tar_path).unwrap());
+        tar.append_path_with_name(&first, "station_status/1.json.gz")
+            .unwrap();
+        tar.append_path_with_name(&plain, "station_status/2.json")
+            .unwrap();
+        tar.append_path_with_name(&plain, "README").unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+
+        let args = Args::parse_from(["bike-1", "--input-tar", &tar_path.display().to_string()]);
+        let mut read = Vec::new();
+        for_each_snapshot(&args, |name, snapshot| {
+            let status = snapshot?.status;
+            read.push((
+                name.to_string(),
+                status.last_updated,
+                status.data.stations.len(),
+            ));
+            Ok(())
+        })
+        .unwrap();
+        let member = |name| format!("{}:station_status/{name}", tar_path.display());
+        assert_eq!(
+            read,
+            [(member("1.json.gz"), T0, 1), (member("2.json"), T0 + 60, 2)]
+        );
+    }
+
+    #[test]
+    fn other_feeds_are_skipped_by_name_or_unreadable_by_content() {
+        let dir = TempDir::new();
+        let status = dir.snapshot(T0, vec![station("A", 1, 0, 1)]);
+        let write = |name: &str, data: serde_json::Value| {
+            let document = json!({"last_updated": T0, "ttl": 60, "data": data});
+            write_gz(&dir.join(format!("station_status/{name}")), &document);
+        };
+        // Skipped by its name alone, whatever it holds.
+        write(
+            "free_bike_status_1688000040.json.gz",
+            json!({"stations": [station("B", 1, 0, 1)]}),
+        );
+        write(
+            "1688000100.json.gz",
+            json!({"bikes": [{"bike_id": "b1", "lat": 40.7, "lon": -74.0}]}),
+        );
+        write(
+            "1688000160.json.gz",
+            json!({"vehicles": [{"vehicle_id": "v1", "lat": 40.7, "lon": -74.0}]}),
+        );
+        let information = json!({"stations": [{"station_id": "A", "name": "Main St", "lat": 40.7, "lon": -74.0}]});
+        write("1688000220.json.gz", information.clone());
+        // Named station_status, so never taken for another feed.
+        write("station_status_1688000280.json.gz", information);
+
+        let args = Args::parse_from(dir.collect_argv(&[]));
+        let mut read = Vec::new();
+        for_each_snapshot(&args, |name, snapshot| {
+            let name = Path::new(name).file_name().unwrap().to_str().unwrap();
+            read.push((name.to_string(), snapshot.err().map(|err| err.to_string())));
+            Ok(())
+        })
+        .unwrap();
+        let looks_like = |feed| Some(format!("looks like a {feed} snapshot, not station_status"));
+        assert_eq!(read[0], ("1688000040.json.gz".to_string(), None));
+        assert_eq!(read[1].1, looks_like("free_bike_status"));
+        assert_eq!(read[2].1, looks_like("vehicle_status"));
+        assert_eq!(read[3].1, looks_like("station_information"));
+        let (name, err) = &read[4];
+        assert_eq!(name, "station_status_1688000280.json.gz");
+        assert!(err.as_ref().unwrap().contains("missing field"), "{err:?}");
+        assert_eq!(read.len(), 5);
+
+        let args = Args::parse_from(dir.collect_argv(&["--on-error", "fail"]));
+        let err = for_each_snapshot(&args, |_, _| Ok(())).unwrap_err();
+        assert!(
+            err.to_string().ends_with(
+                "1688000100.json.gz: looks like a free_bike_status snapshot, not station_status"
+            ),
+            "{err}"
+        );
+        assert_eq!(status.file_name().unwrap(), "1688000040.json.gz");
+
+        // Only the file name counts, not the directories above it.
+        assert_eq!(named_feed("free_bike_status/1688000040.json.gz"), None);
+        assert_eq!(
+            named_feed("station_status/free_bike_status.json"),
+            Some("free_bike_status")
+        );
+        assert_eq!(
+            named_feed("free_bike_status/station_status.json"),
+            Some("station_status")
+        );
+    }
+
+    #[test]
+    fn last_reported_is_read_in_seconds_or_milliseconds() {
+        let last_reported = |value: serde_json::Value| {
+            let mut station = station("A", 1, 0, 1);
+            station["last_reported"] = value;
+            parse(station).last_reported.and_then(epoch_millis)
+        };
+        assert_eq!(last_reported(json!(null)), None);
+        assert_eq!(last_reported(json!(0)), None);
+        assert_eq!(last_reported(json!(-1)), None);
+        assert_eq!(last_reported(json!(T0)), Some(T0 * 1000));
+        assert_eq!(last_reported(json!(T0 * 1000)), Some(T0 * 1000));
+        assert_eq!(
+            epoch_millis(MILLIS_FROM - 1),
+            Some((MILLIS_FROM - 1) * 1000)
+        );
+        assert_eq!(epoch_millis(MILLIS_FROM), Some(MILLIS_FROM));
+    }
+
+    #[test]
+    fn reads_are_read_buffer_size_bytes() {
+        /// Records the largest read asked of it.
+        struct Recording<'a> {
+            inner: &'a [u8],
+            largest: &'a mut usize,
+        }
+        impl Read for Recording<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                *self.largest = (*self.largest).max(buf.len());
+                self.inner.read(buf)
+            }
+        }
+
+        let dir = TempDir::new();
+        let document = document(T0, vec![station("A", 1, 0, 1)]);
+        let path = dir.join("A.json.gz");
+        write_gz(&path, &document);
+        let gzipped = std::fs::read(&path).unwrap();
+        let plain = serde_json::to_vec(&document).unwrap();
+        for size in [64, 1000, 1 << 16] {
+            for (name, file) in [("A.json.gz", &gzipped), ("A.json", &plain)] {
+                let mut largest = 0;
+                let reader = Recording {
+                    inner: file,
+                    largest: &mut largest,
+                };
+                let args = Args::parse_from(["bike-1", "--read-buffer-size", &size.to_string()]);
+                read_snapshot(name, None, reader, &args, None).unwrap();
+                assert_eq!(largest, size, "reading {name}");
+            }
+        }
+    }
+
+    #[test]
+    fn stations_key_reaches_a_nested_array() {
+        let json = serde_json::to_vec(&json!({
+            "last_updated": T0,
+            "data": { "bikes": [station("A", 1, 0, 1), station("B", 2, 1, 0)] },
+        }))
+        .unwrap();
+        let read = |argv: &[&str]| {
+            let args = Args::parse_from(["bike-1"].iter().chain(argv));
+            read_snapshot("bikes.json", None, json.as_slice(), &args, None)
+        };
+
+        let status = read(&["--stations-key", "data.bikes"]).unwrap().status;
+        assert_eq!(status.last_updated, T0);
+        let ids: Vec<&str> = status
+            .data
+            .stations
+            .iter()
+            .map(|station| station.station_id.as_str())
+            .collect();
+        assert_eq!(ids, ["A", "B"]);
+
+        let err = read(&["--stations-key", "data.vehicles"]).err().unwrap();
+        assert_eq!(err.to_string(), "no data.vehicles");
+        // Under the default key it looks like a free_bike_status document.
+        let err = read(&[]).err().unwrap();
+        assert_eq!(
+            err.downcast::<OtherFeed>().unwrap().feed,
+            "free_bike_status"
+        );
+    }
+}
+
+#[cfg(all(test, feature = "simd"))]
+mod simd_tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_fixtures::{document, station, T0};
+
+    #[test]
+    fn parse_fast_agrees_with_serde_json() {
+        let mut broken_down = station("A", 10, 4, 5);
+        broken_down["eightd_has_available_keys"] = json!(true);
+        broken_down["vehicle_types_available"] = json!([
+            {"vehicle_type_id": "1", "count": 6, "num_bikes_disabled": 3},
+            {"vehicle_type_id": "2", "count": 4},
+        ]);
+        let mut sparse = station("B", 0, 0, 12);
+        sparse.as_object_mut().unwrap().remove("last_reported");
+        let json = serde_json::to_vec(&document(T0, vec![broken_down, sparse])).unwrap();
+
+        let fast = parse_fast(&json).expect("simd-json parses it");
+        let slow: StationStatus = serde_json::from_slice(&json).unwrap();
+        assert_eq!(format!("{fast:?}"), format!("{slow:?}"));
+        assert_eq!(fast.data.stations.len(), 2);
+    }
+}
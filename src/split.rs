@@ -0,0 +1,264 @@
+use arrow::array::AsArray;
+use arrow::compute::filter_record_batch;
+use arrow::compute::kernels::comparison::eq_scalar;
+use arrow::datatypes::{DataType, SchemaRef};
+use arrow_array::types::UInt16Type;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::collector::{read_id_map, refuse_overwrite};
+use crate::{Args, Result};
+
+/// One station's output while splitting.
+struct Partition {
+    dir: PathBuf,
+    /// Part files started so far, the last one open if `writer` is.
+    parts: Vec<PathBuf>,
+    writer: Option<ArrowWriter<File>>,
+    /// When this station was last written to, for closing the least
+    /// recently used writer.
+    last_used: u64,
+}
+
+/// Splits an output into `station=<id>/data.parquet` under `out_dir`, one
+/// file per station with the original schema. Directories are named by
+/// provider station id when `--id-map` has it, by the integer id otherwise.
+/// Returns how many stations were written.
+///
+/// With `max_files_open`, at most that many files are open at once. Parquet
+/// can't be appended to, so a station whose writer was closed to make room
+/// gets a new part file when it has rows again, and its parts are merged
+/// into `data.parquet` at the end.
+pub fn split_by_station(
+    args: &Args,
+    path: &Path,
+    out_dir: &Path,
+    max_files_open: Option<usize>,
+) -> Result<usize> {
+    let names: HashMap<u16, String> = if args.id_map.exists() {
+        read_id_map(&args.id_map)?
+            .into_iter()
+            .map(|(name, id)| (id, name))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let schema = builder.schema().clone();
+    // Only the schema version carries over; the stats describe the whole file.
+    let kvs = builder
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .map(|kvs| {
+            kvs.iter()
+                .filter(|kv| kv.key == "schema_version")
+                .cloned()
+                .collect()
+        });
+    let props = WriterProperties::builder()
+        .set_key_value_metadata(kvs)
+        .build();
+    let station_column = schema.index_of("station_ids")?;
+    if schema.field(station_column).data_type() != &DataType::UInt16 {
+        return Err(format!(
+            "{} has dictionary station ids; split-by-station needs a --station-id-type mapped-u16 output",
+            path.display()
+        )
+        .into());
+    }
+    // The input being read takes one of them.
+    let max_writers = max_files_open.map_or(usize::MAX, |max| max.saturating_sub(1).max(1));
+
+    let mut partitions: BTreeMap<u16, Partition> = BTreeMap::new();
+    let mut open = 0;
+    let mut clock = 0;
+    for batch in builder.build()? {
+        let batch = batch?;
+        let station_ids = batch.column(station_column).as_primitive::<UInt16Type>();
+        let stations: BTreeSet<u16> = station_ids.values().iter().copied().collect();
+        for station in stations {
+            let mask = eq_scalar(station_ids, station)?;
+            let rows = filter_record_batch(&batch, &mask)?;
+            if let Entry::Vacant(entry) = partitions.entry(station) {
+                let name = names
+                    .get(&station)
+                    .cloned()
+                    .unwrap_or_else(|| station.to_string());
+                let dir = out_dir.join(format!("station={name}"));
+                let file = dir.join("data.parquet");
+                refuse_overwrite(args, &file)?;
+                fs::create_dir_all(&dir)?;
+                entry.insert(Partition {
+                    dir,
+                    parts: Vec::new(),
+                    writer: None,
+                    last_used: 0,
+                });
+            }
+            if partitions[&station].writer.is_none() {
+                if open >= max_writers {
+                    close_least_recently_used(&mut partitions)?;
+                    open -= 1;
+                }
+                let partition = partitions.get_mut(&station).unwrap();
+                let part = partition
+                    .dir
+                    .join(format!("part-{}.parquet", partition.parts.len()));
+                partition.writer = Some(ArrowWriter::try_new(
+                    File::create(&part)?,
+                    schema.clone(),
+                    Some(props.clone()),
+                )?);
+                partition.parts.push(part);
+                open += 1;
+            }
+            clock += 1;
+            let partition = partitions.get_mut(&station).unwrap();
+            partition.last_used = clock;
+            partition.writer.as_mut().unwrap().write(&rows)?;
+        }
+    }
+
+    let stations = partitions.len();
+    for partition in partitions.into_values() {
+        if let Some(writer) = partition.writer {
+            writer.into_inner()?;
+        }
+        let file = partition.dir.join("data.parquet");
+        match partition.parts.as_slice() {
+            [part] => fs::rename(part, file)?,
+            parts => merge(parts, &file, schema.clone(), props.clone())?,
+        }
+    }
+    Ok(stations)
+}
+
+fn close_least_recently_used(partitions: &mut BTreeMap<u16, Partition>) -> Result<()> {
+    let (station, partition) = partitions
+        .iter_mut()
+        .filter(|(_, partition)| partition.writer.is_some())
+        .min_by_key(|(_, partition)| partition.last_used)
+        .ok_or("no station file open to close")?;
+    debug!(
+        station,
+        "closing station file to stay under --max-files-open"
+    );
+    partition.writer.take().unwrap().into_inner()?;
+    Ok(())
+}
+
+/// Concatenates `parts` into `file` in order, then removes them.
+fn merge(parts: &[PathBuf], file: &Path, schema: SchemaRef, props: WriterProperties) -> Result<()> {
+    let mut writer = ArrowWriter::try_new(File::create(file)?, schema, Some(props))?;
+    for part in parts {
+        for batch in ParquetRecordBatchReaderBuilder::try_new(File::open(part)?)?.build()? {
+            writer.write(&batch?)?;
+        }
+    }
+    writer.into_inner()?;
+    for part in parts {
+        fs::remove_file(part)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::types::TimestampMillisecondType;
+    use arrow_array::RecordBatch;
+    use clap::Parser;
+
+    use super::*;
+    use crate::collector::collect;
+    use crate::test_fixtures::{read_parquet, station, u16_column, TempDir, T0};
+
+    /// (station, time, classic bikes).
+    type Rows = Vec<(u16, i64, Option<u16>)>;
+
+    fn rows(batch: &RecordBatch) -> Rows {
+        let times = batch
+            .column_by_name("times")
+            .unwrap()
+            .as_primitive::<TimestampMillisecondType>();
+        u16_column(batch, "station_ids")
+            .into_iter()
+            .zip(times.values().iter())
+            .zip(u16_column(batch, "num_classic_bikes_available"))
+            .map(|((station, time), bikes)| (station.unwrap(), *time, bikes))
+            .collect()
+    }
+
+    /// Collects `stations` stations over three snapshots, then splits the
+    /// output under `stations/` with `extra` flags, returning the original
+    /// rows and those read back from every station's file.
+    fn split(dir: &TempDir, stations: usize, extra: &[&str]) -> (Rows, Rows) {
+        let names: Vec<String> = (0..stations).map(|n| format!("S{n}")).collect();
+        for (offset, time) in [T0, T0 + 60, T0 + 120].into_iter().enumerate() {
+            let snapshot = names
+                .iter()
+                .enumerate()
+                .map(|(n, name)| station(name, (offset + n) as i64, (n % 2) as i64, 1))
+                .collect();
+            dir.snapshot(time, snapshot);
+        }
+        collect(&Args::parse_from(dir.collect_argv(&[]))).unwrap();
+        let (output, out_dir) = (dir.output(), dir.join("stations"));
+        let argv = [
+            "bike-1",
+            "split-by-station",
+            &output.display().to_string(),
+            "--out-dir",
+            &out_dir.display().to_string(),
+            "--id-map",
+            &dir.id_map().display().to_string(),
+        ];
+        let args = Args::parse_from(argv.iter().copied().chain(extra.iter().copied()));
+        let Some(crate::args::Command::SplitByStation { max_files_open, .. }) = &args.command
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            split_by_station(&args, &output, &out_dir, *max_files_open).unwrap(),
+            stations
+        );
+
+        let mut original = rows(&read_parquet(&output));
+        let mut reassembled = Vec::new();
+        for name in &names {
+            let file = out_dir.join(format!("station={name}/data.parquet"));
+            reassembled.extend(rows(&read_parquet(&file)));
+        }
+        original.sort_unstable();
+        reassembled.sort_unstable();
+        (original, reassembled)
+    }
+
+    #[test]
+    fn split_files_reassemble_into_the_original_rows() {
+        let dir = TempDir::new();
+        let (original, reassembled) = split(&dir, 3, &[]);
+        assert_eq!(original.len(), 9);
+        assert_eq!(reassembled, original);
+    }
+
+    #[test]
+    fn a_small_max_files_open_still_gives_every_station_its_rows() {
+        let dir = TempDir::new();
+        // More rows than a read batch, so the second batch reopens every
+        // station's file after the one writer allowed has moved on.
+        let (original, reassembled) = split(&dir, 400, &["--max-files-open", "2"]);
+        assert_eq!(reassembled, original);
+        let leftover = glob::glob(&dir.join("stations/*/part-*").display().to_string())
+            .unwrap()
+            .count();
+        assert_eq!(leftover, 0, "part files are merged away");
+    }
+}
@@ -0,0 +1,195 @@
+//! Snapshot fixtures shared by the unit tests and, through `#[path]`, the
+//! command line tests under `tests/`, so it only uses the crate's
+//! dependencies and not the crate itself.
+
+// Each test binary uses only some of them.
+#![allow(dead_code)]
+
+use arrow::array::AsArray;
+use arrow::compute::{cast, concat_batches};
+use arrow::datatypes::DataType;
+use arrow::datatypes::{TimestampMillisecondType, UInt16Type};
+use arrow_array::{RecordBatch, RecordBatchReader};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::file::metadata::ParquetMetaData;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A snapshot time on a minute boundary, in seconds since the epoch, so
+/// every `--time-rounding` leaves it alone.
+pub const T0: i64 = 1_688_000_040;
+
+/// A directory under the system temp dir, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "bike-1-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(path.join("station_status")).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+
+    /// Where `collect_argv` writes the output.
+    pub fn output(&self) -> PathBuf {
+        self.join("data.parquet")
+    }
+
+    pub fn id_map(&self) -> PathBuf {
+        self.join("id_map.json")
+    }
+
+    /// Writes a snapshot of `stations` at `last_updated` to
+    /// `station_status/<last_updated>.json.gz`, like the downloader names
+    /// them.
+    pub fn snapshot(&self, last_updated: i64, stations: Vec<Value>) -> PathBuf {
+        let path = self.join(format!("station_status/{last_updated}.json.gz"));
+        write_gz(&path, &document(last_updated, stations));
+        path
+    }
+
+    /// The arguments of a collecting run over the snapshots `snapshot`
+    /// wrote, into `output` and `id_map`, followed by `extra`.
+    pub fn collect_argv(&self, extra: &[&str]) -> Vec<String> {
+        let input = self.join("station_status/*.json.gz");
+        let base = [
+            "bike-1".to_string(),
+            "--input".to_string(),
+            input.display().to_string(),
+            "--output".to_string(),
+            self.output().display().to_string(),
+            "--id-map".to_string(),
+            self.id_map().display().to_string(),
+        ];
+        base.into_iter()
+            .chain(extra.iter().map(|arg| arg.to_string()))
+            .collect()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// An active station_status station with every field the collector reads,
+/// no disabled bikes or docks and no `vehicle_types_available`.
+pub fn station(station_id: &str, bikes: i64, ebikes: i64, docks: i64) -> Value {
+    json!({
+        "station_id": station_id,
+        "num_bikes_available": bikes,
+        "num_ebikes_available": ebikes,
+        "num_bikes_disabled": 0,
+        "num_docks_available": docks,
+        "num_docks_disabled": 0,
+        "is_installed": 1,
+        "is_renting": 1,
+        "is_returning": 1,
+        "last_reported": T0,
+        "station_status": "active",
+    })
+}
+
+/// A station_status document holding `stations`.
+pub fn document(last_updated: i64, stations: Vec<Value>) -> Value {
+    json!({
+        "last_updated": last_updated,
+        "ttl": 60,
+        "data": { "stations": stations },
+    })
+}
+
+/// Writes `document` to `path` gzipped.
+pub fn write_gz(path: &Path, document: &Value) {
+    let mut encoder = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+    encoder
+        .write_all(&serde_json::to_vec(document).unwrap())
+        .unwrap();
+    encoder.finish().unwrap();
+}
+
+/// Every row of a parquet file, in one batch.
+pub fn read_parquet(path: &Path) -> RecordBatch {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+        .unwrap()
+        .build()
+        .unwrap();
+    // The batches' schema, which leaves out the footer's key-values.
+    let schema = reader.schema();
+    let batches: Vec<RecordBatch> = reader.map(Result::unwrap).collect();
+    concat_batches(&schema, &batches).unwrap()
+}
+
+/// A `UInt16` column of `batch` by name, nulls as `None`.
+pub fn u16_column(batch: &RecordBatch, name: &str) -> Vec<Option<u16>> {
+    batch
+        .column_by_name(name)
+        .unwrap_or_else(|| panic!("no {name} column"))
+        .as_primitive::<UInt16Type>()
+        .iter()
+        .collect()
+}
+
+/// The `times` column of `batch`, in milliseconds since the epoch.
+pub fn times(batch: &RecordBatch) -> Vec<i64> {
+    batch
+        .column_by_name("times")
+        .expect("no times column")
+        .as_primitive::<TimestampMillisecondType>()
+        .values()
+        .to_vec()
+}
+
+/// A string column of `batch` by name, plain or dictionary encoded.
+pub fn string_column(batch: &RecordBatch, name: &str) -> Vec<String> {
+    let column = batch
+        .column_by_name(name)
+        .unwrap_or_else(|| panic!("no {name} column"));
+    cast(column, &DataType::Utf8)
+        .unwrap()
+        .as_string::<i32>()
+        .iter()
+        .map(|value| value.unwrap().to_string())
+        .collect()
+}
+
+/// Reads a JSON file.
+pub fn read_json(path: &Path) -> Value {
+    serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+}
+
+/// A parquet file's footer.
+pub fn metadata(path: &Path) -> ParquetMetaData {
+    let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+    reader.metadata().clone()
+}
+
+/// A key-value from a parquet file's footer.
+pub fn footer_value(path: &Path, key: &str) -> Option<String> {
+    metadata(path)
+        .file_metadata()
+        .key_value_metadata()?
+        .iter()
+        .find(|kv| kv.key == key)
+        .and_then(|kv| kv.value.clone())
+}
@@ -0,0 +1,83 @@
+use arrow::datatypes::Field;
+use arrow_array::builder::{make_builder, ArrayBuilder};
+use arrow_array::ArrayRef;
+use chrono::NaiveDateTime;
+
+use crate::snapshot::Station;
+
+/// Adds derived columns to the parquet output, for columns too specific to
+/// one system to belong in the crate. Register one with
+/// [`CollectorBuilder::transform`](crate::collector::CollectorBuilder::transform);
+/// its columns follow the built-in ones.
+///
+/// `apply` is called once for every row written and must append exactly one
+/// value to each of its columns, nulls included.
+///
+/// ```no_run
+/// use arrow::datatypes::{DataType, Field};
+/// use arrow_array::builder::BooleanBuilder;
+/// use bike_1::snapshot::Station;
+/// use bike_1::transform::{RowBuilder, RowTransform};
+/// use chrono::{Datelike, NaiveDateTime, Weekday};
+/// use clap::Parser;
+///
+/// struct Weekend;
+///
+/// impl RowTransform for Weekend {
+///     fn fields(&self) -> Vec<Field> {
+///         vec![Field::new("is_weekend", DataType::Boolean, false)]
+///     }
+///
+///     fn apply(&self, _station: &Station, time: NaiveDateTime, out: &mut RowBuilder) {
+///         let weekend = matches!(time.weekday(), Weekday::Sat | Weekday::Sun);
+///         out.column::<BooleanBuilder>(0).append_value(weekend);
+///     }
+/// }
+///
+/// # fn main() -> bike_1::Result<()> {
+/// let args = bike_1::Args::parse_from(["collect", "--output", "data.parquet"]);
+/// let summary = bike_1::Collector::builder(&args)
+///     .transform(Weekend)
+///     .collect()?;
+/// println!("{} rows", summary.rows);
+/// # Ok(())
+/// # }
+/// ```
+pub trait RowTransform {
+    /// The columns this transform adds.
+    fn fields(&self) -> Vec<Field>;
+
+    /// Appends this row's values, given the station and the snapped
+    /// snapshot time.
+    fn apply(&self, station: &Station, time: NaiveDateTime, out: &mut RowBuilder);
+}
+
+/// Column builders for one transform's columns, in the order of its
+/// `fields`.
+pub struct RowBuilder {
+    columns: Vec<Box<dyn ArrayBuilder>>,
+}
+
+impl RowBuilder {
+    pub(crate) fn new(fields: &[Field]) -> Self {
+        RowBuilder {
+            columns: fields
+                .iter()
+                .map(|field| make_builder(field.data_type(), 0))
+                .collect(),
+        }
+    }
+
+    /// The builder for the `index`th column. Panics if `B` isn't the builder
+    /// for that column's data type, e.g. `UInt16Builder` for `UInt16`.
+    pub fn column<B: ArrayBuilder>(&mut self, index: usize) -> &mut B {
+        self.columns[index]
+            .as_any_mut()
+            .downcast_mut()
+            .expect("builder type doesn't match the column's data type")
+    }
+
+    pub(crate) fn finish(&mut self) -> impl Iterator<Item = ArrayRef> + '_ {
+        self.columns.iter_mut().map(|column| column.finish())
+    }
+}
@@ -0,0 +1,64 @@
+use arrow::datatypes::{DataType, Field};
+use arrow_array::builder::UInt16Builder;
+use chrono::NaiveDateTime;
+use std::collections::BTreeSet;
+
+use crate::snapshot::{for_each_snapshot, Station};
+use crate::transform::{RowBuilder, RowTransform};
+use crate::{Args, Result};
+
+/// One `avail_<vehicle_type_id>` column per vehicle type, for
+/// `--split-vehicle-availability-by-type`.
+pub(crate) struct VehicleTypeCounts {
+    type_ids: Vec<String>,
+}
+
+impl VehicleTypeCounts {
+    pub fn new(type_ids: Vec<String>) -> Self {
+        VehicleTypeCounts { type_ids }
+    }
+}
+
+impl RowTransform for VehicleTypeCounts {
+    fn fields(&self) -> Vec<Field> {
+        self.type_ids
+            .iter()
+            .map(|type_id| Field::new(format!("avail_{type_id}"), DataType::UInt16, true))
+            .collect()
+    }
+
+    /// A type the station doesn't list has none available. A count that
+    /// doesn't fit a u16 is null.
+    fn apply(&self, station: &Station, _time: NaiveDateTime, out: &mut RowBuilder) {
+        for (index, type_id) in self.type_ids.iter().enumerate() {
+            let count = station
+                .vehicle_types_available
+                .iter()
+                .find(|vehicle_type| &vehicle_type.vehicle_type_id == type_id)
+                .map_or(Some(0), |vehicle_type| {
+                    u16::try_from(vehicle_type.count.unwrap_or(0)).ok()
+                });
+            out.column::<UInt16Builder>(index).append_option(count);
+        }
+    }
+}
+
+/// Every vehicle type id listed by any station in the inputs, read in a
+/// pass of its own before collecting.
+pub(crate) fn discover(args: &Args) -> Result<Vec<String>> {
+    let mut type_ids = BTreeSet::new();
+    for_each_snapshot(args, |_, snapshot| {
+        // Unreadable snapshots are reported by the collecting pass.
+        if let Ok(snapshot) = snapshot {
+            for station in &snapshot.status.data.stations {
+                for vehicle_type in &station.vehicle_types_available {
+                    if !type_ids.contains(&vehicle_type.vehicle_type_id) {
+                        type_ids.insert(vehicle_type.vehicle_type_id.clone());
+                    }
+                }
+            }
+        }
+        Ok(())
+    })?;
+    Ok(type_ids.into_iter().collect())
+}
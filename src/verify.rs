@@ -0,0 +1,222 @@
+use arrow::array::as_primitive_array;
+use arrow::datatypes::{DataType, UInt16Type};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ProjectionMask;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::File;
+use std::path::Path;
+
+use crate::collector::read_id_map;
+use crate::row::{schema, Columns};
+use crate::Result;
+
+/// Nulls found in one column of an output.
+#[derive(Debug, Serialize)]
+pub struct ColumnNulls {
+    pub column: String,
+    pub nulls: usize,
+    /// Whether the collector's schema says this column is never null.
+    pub required: bool,
+}
+
+impl ColumnNulls {
+    pub fn is_violation(&self) -> bool {
+        self.required && self.nulls > 0
+    }
+}
+
+/// Columns the collector never writes a null to, under any combination of
+/// optional columns. Taken from the current schema rather than the file's,
+/// so a file whose own schema was loosened is still caught.
+fn required_columns() -> HashSet<String> {
+    [false, true]
+        .into_iter()
+        .flat_map(|flag| {
+            schema(&Columns {
+                source_file: flag,
+                usable_bikes: flag,
+                classic_bikes: flag,
+                station_id_dictionary: flag,
+                last_reported: flag,
+                eightd_has_available_keys: flag,
+                rolling_occupancy: flag,
+                ..Columns::default()
+            })
+            .fields()
+            .iter()
+            .filter(|field| !field.is_nullable())
+            .map(|field| field.name().clone())
+            .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Counts the nulls in every column by scanning the file.
+pub fn null_counts(path: &Path) -> Result<Vec<ColumnNulls>> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let required = required_columns();
+    let mut counts: Vec<ColumnNulls> = builder
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| ColumnNulls {
+            column: field.name().clone(),
+            nulls: 0,
+            required: required.contains(field.name()),
+        })
+        .collect();
+    for batch in builder.build()? {
+        for (count, column) in counts.iter_mut().zip(batch?.columns()) {
+            count.nulls += column.null_count();
+        }
+    }
+    Ok(counts)
+}
+
+/// How an output's `station_ids` and an id map disagree.
+#[derive(Debug, Serialize)]
+pub struct Consistency {
+    /// Distinct ids in the output.
+    pub stations: usize,
+    /// Ids in the output that no station in the map has.
+    pub unmapped_ids: Vec<u16>,
+    /// Ids that more than one station in the map has.
+    pub shared_ids: BTreeMap<u16, Vec<String>>,
+    /// Stations in the map with no rows in the output, by id.
+    pub absent_stations: BTreeMap<u16, String>,
+}
+
+impl Consistency {
+    pub fn is_consistent(&self) -> bool {
+        self.unmapped_ids.is_empty() && self.shared_ids.is_empty()
+    }
+}
+
+/// Compares the ids in the parquet output at `parquet` with the id map at
+/// `id_map`, reading only the `station_ids` column.
+pub fn check_consistency(parquet: &Path, id_map: &Path) -> Result<Consistency> {
+    let legend = read_id_map(id_map)?;
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(parquet)?)?;
+    let index = builder.schema().index_of("station_ids")?;
+    if builder.schema().field(index).data_type() != &DataType::UInt16 {
+        return Err(format!(
+            "{}'s station_ids aren't mapped integers, so it has no id map to check",
+            parquet.display()
+        )
+        .into());
+    }
+    let mask = ProjectionMask::roots(builder.parquet_schema(), [index]);
+    let mut ids = BTreeSet::new();
+    for batch in builder.with_projection(mask).build()? {
+        let batch = batch?;
+        ids.extend(
+            as_primitive_array::<UInt16Type>(batch.column(0))
+                .values()
+                .iter(),
+        );
+    }
+
+    let mut stations: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+    for (station_id, id) in legend {
+        stations.entry(id).or_default().push(station_id);
+    }
+    let unmapped_ids = ids
+        .iter()
+        .copied()
+        .filter(|id| !stations.contains_key(id))
+        .collect();
+    let mut shared_ids = BTreeMap::new();
+    let mut absent_stations = BTreeMap::new();
+    for (id, mut station_ids) in stations {
+        if station_ids.len() > 1 {
+            station_ids.sort_unstable();
+            shared_ids.insert(id, station_ids);
+        } else if !ids.contains(&id) {
+            absent_stations.insert(id, station_ids.remove(0));
+        }
+    }
+    Ok(Consistency {
+        stations: ids.len(),
+        unmapped_ids,
+        shared_ids,
+        absent_stations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::datatypes::{Field, Schema};
+    use arrow_array::{ArrayRef, RecordBatch, UInt16Array};
+    use clap::Parser;
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::collector::collect;
+    use crate::test_fixtures::{station, TempDir, T0};
+    use crate::Args;
+
+    fn violations(path: &Path) -> Vec<String> {
+        null_counts(path)
+            .unwrap()
+            .into_iter()
+            .filter(ColumnNulls::is_violation)
+            .map(|count| count.column)
+            .collect()
+    }
+
+    #[test]
+    fn a_collected_output_has_no_violations() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1), station("B", -1, 0, 1)]);
+        collect(&Args::parse_from(dir.collect_argv(&[]))).unwrap();
+        assert!(violations(&dir.output()).is_empty());
+    }
+
+    #[test]
+    fn a_null_station_id_is_a_violation() {
+        let dir = TempDir::new();
+        // A file whose schema lets station_ids be null, as a corrupt writer
+        // might leave it.
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("station_ids", DataType::UInt16, true),
+            Field::new("num_docks_available", DataType::UInt16, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(UInt16Array::from(vec![Some(1), None])) as ArrayRef,
+                Arc::new(UInt16Array::from(vec![None, Some(2)])),
+            ],
+        )
+        .unwrap();
+        let path = dir.output();
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        assert_eq!(violations(&path), ["station_ids"]);
+    }
+
+    #[test]
+    fn a_station_missing_from_the_map_is_inconsistent() {
+        let dir = TempDir::new();
+        dir.snapshot(T0, vec![station("A", 1, 0, 1), station("B", 2, 0, 1)]);
+        collect(&Args::parse_from(dir.collect_argv(&[]))).unwrap();
+        let consistency = check_consistency(&dir.output(), &dir.id_map()).unwrap();
+        assert!(consistency.is_consistent(), "{consistency:?}");
+        assert_eq!(consistency.stations, 2);
+
+        let mut legend = read_id_map(&dir.id_map()).unwrap();
+        let b = legend.remove("B").unwrap();
+        legend.insert("Z".to_string(), 9);
+        std::fs::write(dir.id_map(), serde_json::to_vec(&legend).unwrap()).unwrap();
+        let consistency = check_consistency(&dir.output(), &dir.id_map()).unwrap();
+        assert!(!consistency.is_consistent());
+        assert_eq!(consistency.unmapped_ids, [b]);
+        assert_eq!(
+            consistency.absent_stations,
+            BTreeMap::from([(9, "Z".to_string())])
+        );
+    }
+}
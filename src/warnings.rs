@@ -0,0 +1,106 @@
+use serde::Serialize;
+use std::fs::File;
+use std::path::Path;
+use tracing::warn;
+
+/// What the collector adjusted or dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// A snapshot `--on-error skip` passed over.
+    UnreadableSnapshot,
+    /// A snapshot older than one already collected.
+    BackwardsTime,
+    /// A snapshot under `--min-stations`.
+    TooFewStations,
+    /// A second snapshot for a minute already collected.
+    DuplicateMinute,
+    /// A count stored as null for being negative or above its maximum.
+    CountOutOfRange,
+    /// An `is_returning` stored as null for not being 0 or 1.
+    InvalidIsReturning,
+}
+
+impl WarningKind {
+    fn message(self) -> &'static str {
+        match self {
+            WarningKind::UnreadableSnapshot => "skipping unreadable snapshot",
+            WarningKind::BackwardsTime => "snapshot time went backwards",
+            WarningKind::TooFewStations => "skipping snapshot with too few active stations",
+            WarningKind::DuplicateMinute => "skipping snapshot for an already ingested minute",
+            WarningKind::CountOutOfRange => "count out of range, storing null",
+            WarningKind::InvalidIsReturning => "is_returning is not 0 or 1",
+        }
+    }
+}
+
+/// One data-quality adjustment, as written by `--warnings-out`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Warning {
+    pub file: String,
+    pub station_id: Option<String>,
+    /// The snapshot's snapped time in milliseconds since the epoch, if it
+    /// got that far.
+    pub time: Option<i64>,
+    pub kind: WarningKind,
+    pub detail: String,
+}
+
+/// Logs every warning about the data and, with `--warnings-out`, keeps
+/// them. Warnings are attributed to the snapshot last passed to `locate`.
+#[derive(Debug, Default)]
+pub struct Warnings {
+    keep: bool,
+    file: String,
+    time: Option<i64>,
+    count: usize,
+    kept: Vec<Warning>,
+}
+
+impl Warnings {
+    pub fn new(keep: bool) -> Self {
+        Warnings {
+            keep,
+            ..Warnings::default()
+        }
+    }
+
+    /// Sets the snapshot later warnings are about.
+    pub fn locate(&mut self, file: &str, time: Option<i64>) {
+        if self.file != file {
+            self.file = file.to_string();
+        }
+        self.time = time;
+    }
+
+    pub fn report(&mut self, station_id: Option<&str>, kind: WarningKind, detail: String) {
+        warn!(
+            file = %self.file,
+            station_id,
+            time = self.time,
+            %detail,
+            "{}",
+            kind.message()
+        );
+        self.count += 1;
+        if self.keep {
+            self.kept.push(Warning {
+                file: self.file.clone(),
+                station_id: station_id.map(String::from),
+                time: self.time,
+                kind,
+                detail,
+            });
+        }
+    }
+
+    /// Warnings reported so far, kept or not.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        serde_json::to_writer_pretty(File::create(path)?, &self.kept)?;
+        Ok(())
+    }
+}
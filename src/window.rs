@@ -0,0 +1,106 @@
+use arrow::array::as_primitive_array;
+use arrow::compute::filter_record_batch;
+use arrow::compute::kernels::aggregate::max;
+use arrow::compute::kernels::comparison::gt_eq_scalar;
+use arrow::datatypes::{SchemaRef, TimestampMillisecondType};
+use arrow_array::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::{ArrowWriter, ProjectionMask};
+use std::fs::File;
+use std::path::Path;
+
+use crate::snapshot::for_each_time;
+use crate::{Args, Result};
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Where `--window-days` starts, and the newest row the existing output
+/// already has.
+pub(crate) struct SlidingWindow {
+    /// Rows from before this, in milliseconds since the epoch, are dropped.
+    pub start: i64,
+    /// Snapshots up to this are already in the existing output.
+    pub latest: Option<i64>,
+    /// The index of `times` in the schema.
+    times: usize,
+}
+
+impl SlidingWindow {
+    /// A window of `days` back from the newest time in the existing output
+    /// or the inputs, read from the inputs in a pass of their own. The
+    /// existing output must have the columns `schema` has.
+    pub fn new(args: &Args, days: u32, schema: &SchemaRef, times: usize) -> Result<Self> {
+        let latest = if args.output.exists() {
+            latest_time(&args.output, schema, times)?
+        } else {
+            None
+        };
+        let mut newest = latest;
+        for_each_time(args, |_, time| {
+            // Unreadable snapshots are reported by the collecting pass.
+            if let Ok(time) = time {
+                let time = if args.raw {
+                    time
+                } else {
+                    args.time_rounding.apply(time.div_euclid(1000)) * 1000
+                };
+                newest = newest.max(Some(time));
+            }
+            Ok(())
+        })?;
+        Ok(SlidingWindow {
+            start: newest
+                .unwrap_or(i64::MIN)
+                .saturating_sub(i64::from(days) * DAY_MS),
+            latest,
+            times,
+        })
+    }
+
+    /// Writes the rows of the existing output at `path` from the window's
+    /// start on to `writer`, handing each batch written to `f`. Returns how
+    /// many rows were kept and how many dropped.
+    pub fn carry_over(
+        &self,
+        path: &Path,
+        schema: &SchemaRef,
+        writer: &mut ArrowWriter<File>,
+        mut f: impl FnMut(&RecordBatch),
+    ) -> Result<(usize, usize)> {
+        let (mut kept, mut dropped) = (0, 0);
+        for batch in ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()? {
+            let batch = batch?;
+            let times = as_primitive_array::<TimestampMillisecondType>(batch.column(self.times));
+            let batch = filter_record_batch(&batch, &gt_eq_scalar(times, self.start)?)?;
+            let batch = RecordBatch::try_new(schema.clone(), batch.columns().to_vec())?;
+            dropped += times.len() - batch.num_rows();
+            kept += batch.num_rows();
+            writer.write(&batch)?;
+            f(&batch);
+        }
+        writer.flush()?;
+        Ok((kept, dropped))
+    }
+}
+
+/// The newest `times` in the parquet file at `path`, checking it has the
+/// columns `schema` has.
+fn latest_time(path: &Path, schema: &SchemaRef, times: usize) -> Result<Option<i64>> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    if builder.schema().fields() != schema.fields() {
+        return Err(format!(
+            "{} has different columns from this run's, so --window-days can't keep its rows; move it aside to start a new window",
+            path.display()
+        )
+        .into());
+    }
+    let mask = ProjectionMask::roots(builder.parquet_schema(), [times]);
+    let mut latest = None;
+    for batch in builder.with_projection(mask).build()? {
+        let batch = batch?;
+        latest = latest.max(max(as_primitive_array::<TimestampMillisecondType>(
+            batch.column(0),
+        )));
+    }
+    Ok(latest)
+}
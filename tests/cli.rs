@@ -0,0 +1,162 @@
+//! Runs the binary, for what only `main` does: logging, the summary and the
+//! exit status.
+
+#[path = "../src/test_fixtures.rs"]
+mod test_fixtures;
+
+use std::process::{Command, Output};
+
+use test_fixtures::{document, station, write_gz, TempDir, T0};
+
+/// Runs the binary with `argv`, leaving out its first element, the program
+/// name.
+fn bike_1(argv: &[String]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bike-1"))
+        .args(&argv[1..])
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn quiet_prints_nothing() {
+    let dir = TempDir::new();
+    dir.snapshot(T0, vec![station("A", 3, 1, 5)]);
+    let output = bike_1(&dir.collect_argv(&["--quiet"]));
+    assert!(output.status.success());
+    assert!(
+        output.stdout.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn json_summary_is_one_object() {
+    let dir = TempDir::new();
+    dir.snapshot(T0, vec![station("A", 3, 1, 5), station("B", 2, 0, 7)]);
+    let output = bike_1(&dir.collect_argv(&["--log-format", "json"]));
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // Log events are JSON too, one per line, and the summary comes last.
+    let lines: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let summaries: Vec<_> = lines
+        .iter()
+        .filter(|line| line.get("level").is_none())
+        .collect();
+    assert_eq!(summaries.len(), 1);
+    let summary = lines.last().unwrap();
+    assert_eq!(summary["files"], 1);
+    assert_eq!(summary["rows"], 2);
+    assert_eq!(summary["stations"], 2);
+}
+
+#[test]
+fn dry_run_writes_nothing_and_refuses_to_overwrite() {
+    let dir = TempDir::new();
+    dir.snapshot(T0, vec![station("A", 3, 1, 5)]);
+    let argv = dir.collect_argv(&["--dry-run"]);
+    let output = bike_1(&argv);
+    assert!(output.status.success());
+    let written: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(written, ["station_status"]);
+
+    std::fs::write(dir.output(), "").unwrap();
+    let output = bike_1(&argv);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("refusing to overwrite"), "{stdout}");
+}
+
+#[test]
+fn time_span_reports_the_earliest_and_latest_snapshot() {
+    let dir = TempDir::new();
+    dir.snapshot(T0 + 60, vec![station("A", 3, 1, 5)]);
+    dir.snapshot(T0, vec![station("A", 2, 1, 6)]);
+    // Not named for its time, so `last_updated` is read.
+    write_gz(
+        &dir.join("station_status/latest.json.gz"),
+        &document(T0 + 3600, vec![station("A", 1, 1, 7)]),
+    );
+    std::fs::write(dir.join("station_status/broken.json.gz"), "not gzip").unwrap();
+    // Other feeds count no more than collecting would take them.
+    write_gz(
+        &dir.join("station_status/free_bike_status_1688100000.json.gz"),
+        &document(T0 + 7200, vec![station("A", 1, 1, 7)]),
+    );
+    let information = serde_json::json!({"last_updated": T0 + 7200, "ttl": 60, "data": {
+        "stations": [{"station_id": "A", "name": "Main St", "lat": 40.7, "lon": -74.0}],
+    }});
+    write_gz(&dir.join("station_status/1688100000.json.gz"), &information);
+    let output = bike_1(&dir.collect_argv(&["--time-span", "--log-format", "json"]));
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let span: serde_json::Value = stdout
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|line| line["fields"]["message"] == "time span")
+        .unwrap_or_else(|| panic!("no time span in {stdout}"));
+    assert_eq!(span["fields"]["snapshots"], 3);
+    assert_eq!(span["fields"]["earliest"], "2023-06-29 00:54:00");
+    assert_eq!(span["fields"]["latest"], "2023-06-29 01:54:00");
+    assert!(!dir.output().exists());
+}
+
+#[test]
+fn fail_on_warning_fails_a_run_that_adjusted_the_data() {
+    let dir = TempDir::new();
+    // A sentinel count, stored as null with a warning.
+    dir.snapshot(T0, vec![station("A", 20_000, 1, 5)]);
+    let output = bike_1(&dir.collect_argv(&[]));
+    assert!(output.status.success());
+
+    std::fs::remove_file(dir.output()).unwrap();
+    let output = bike_1(&dir.collect_argv(&["--overwrite", "--fail-on-warning"]));
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("1 warning(s) under --fail-on-warning"),
+        "{stdout}"
+    );
+    // The outputs are still written, for looking into the warning.
+    assert!(dir.output().exists());
+
+    dir.snapshot(T0, vec![station("A", 2, 1, 5)]);
+    let output = bike_1(&dir.collect_argv(&["--overwrite", "--fail-on-warning"]));
+    assert!(output.status.success());
+}
+
+#[test]
+fn check_consistency_fails_on_a_station_missing_from_the_map() {
+    let dir = TempDir::new();
+    dir.snapshot(T0, vec![station("A", 3, 1, 5), station("B", 2, 0, 7)]);
+    assert!(bike_1(&dir.collect_argv(&[])).status.success());
+    let argv = |dir: &TempDir| -> Vec<String> {
+        [
+            "bike-1",
+            "check-consistency",
+            "--parquet",
+            &dir.output().display().to_string(),
+            "--id-map",
+            &dir.id_map().display().to_string(),
+        ]
+        .map(String::from)
+        .to_vec()
+    };
+    assert!(bike_1(&argv(&dir)).status.success());
+
+    let mut legend: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(&std::fs::read(dir.id_map()).unwrap()).unwrap();
+    legend.remove("B").unwrap();
+    std::fs::write(dir.id_map(), serde_json::to_vec(&legend).unwrap()).unwrap();
+    let output = bike_1(&argv(&dir));
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("1 id(s) missing from the map"), "{stdout}");
+}